@@ -0,0 +1,631 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::{MEMORY_SIZE, PROGRAM_START};
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+const KEYWORDS: [&str; 9] = ["i", "dt", "st", "k", "f", "hf", "b", "r", "long"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblerError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssemblerError {}
+
+struct SourceLine {
+    file: String,
+    line: usize,
+    text: String,
+}
+
+impl SourceLine {
+    fn error(&self, message: impl Into<String>) -> AssemblerError {
+        AssemblerError {
+            file: self.file.clone(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+}
+
+enum Statement<'a> {
+    Instruction(&'a str, Vec<&'a str>),
+    Bytes(Vec<&'a str>),
+    Words(Vec<&'a str>),
+}
+
+enum Operand {
+    Register(u8),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Rpl,
+    Value(u16),
+    Long(u16),
+}
+
+/// Assembles a source file, `include` paths are resolved relative to it.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AssemblerError> {
+    let mut lines = Vec::new();
+    read_source(path, None, 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+/// Assembles source text, `include` paths are resolved relative to the working directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblerError> {
+    let mut lines = Vec::new();
+    split_source("<source>", source, Path::new("."), 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+fn read_source(
+    path: &Path,
+    included_from: Option<&SourceLine>,
+    depth: usize,
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), AssemblerError> {
+    let source = fs::read_to_string(path).map_err(|error| {
+        let message = format!("couldn't read {}: {error}", path.display());
+        match included_from {
+            Some(line) => line.error(message),
+            None => AssemblerError {
+                file: path.display().to_string(),
+                line: 0,
+                message,
+            },
+        }
+    })?;
+    let directory = path.parent().unwrap_or(Path::new("."));
+    split_source(
+        &path.display().to_string(),
+        &source,
+        directory,
+        depth,
+        lines,
+    )
+}
+
+fn split_source(
+    file: &str,
+    source: &str,
+    directory: &Path,
+    depth: usize,
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), AssemblerError> {
+    for (index, text) in source.lines().enumerate() {
+        let line = SourceLine {
+            file: file.to_string(),
+            line: index + 1,
+            text: strip_comment(text).trim().to_string(),
+        };
+
+        let include = line
+            .text
+            .split_once(char::is_whitespace)
+            .filter(|(directive, _)| directive.eq_ignore_ascii_case("include"))
+            .map(|(_, path)| path.trim());
+        match include {
+            Some(path) => {
+                if depth == MAX_INCLUDE_DEPTH {
+                    return Err(line.error("includes are nested too deeply"));
+                }
+                let path = path
+                    .strip_prefix('"')
+                    .and_then(|path| path.strip_suffix('"'))
+                    .ok_or_else(|| line.error("include path must be quoted"))?;
+                let path: PathBuf = directory.join(path);
+                read_source(&path, Some(&line), depth + 1, lines)?;
+            }
+            None => lines.push(line),
+        }
+    }
+    Ok(())
+}
+
+fn strip_comment(text: &str) -> &str {
+    text.split_once(';').map_or(text, |(code, _)| code)
+}
+
+fn assemble_lines(lines: &[SourceLine]) -> Result<Vec<u8>, AssemblerError> {
+    // First pass: find label addresses and constants
+    let mut symbols = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = PROGRAM_START as usize;
+
+    for line in lines {
+        let mut text = line.text.as_str();
+
+        if let Some((name, value)) = text.split_once('=') {
+            let name = name.trim();
+            check_symbol_name(line, name)?;
+            let value = evaluate(line, value.trim(), &symbols)?;
+            if symbols.insert(name.to_ascii_lowercase(), value).is_some() {
+                return Err(line.error(format!("{name} is already defined")));
+            }
+            continue;
+        }
+
+        if let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            check_symbol_name(line, label)?;
+            if symbols
+                .insert(label.to_ascii_lowercase(), address as u16)
+                .is_some()
+            {
+                return Err(line.error(format!("{label} is already defined")));
+            }
+            text = rest.trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+        let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, split_operands(operands)),
+            None => (text, Vec::new()),
+        };
+        let statement = match mnemonic.to_ascii_lowercase().as_str() {
+            "db" => Statement::Bytes(operands),
+            "dw" => Statement::Words(operands),
+            _ => Statement::Instruction(mnemonic, operands),
+        };
+        address += match &statement {
+            Statement::Bytes(values) => values.len(),
+            Statement::Words(values) => values.len() * 2,
+            Statement::Instruction(_, operands) => instruction_len(operands),
+        };
+        if address > MEMORY_SIZE {
+            return Err(line.error("program doesn't fit in memory"));
+        }
+        statements.push((line, statement));
+    }
+
+    // Second pass: encode with every symbol known
+    let mut rom = Vec::new();
+    for (line, statement) in statements {
+        match statement {
+            Statement::Bytes(values) => {
+                for value in values {
+                    let value = evaluate(line, value, &symbols)?;
+                    rom.push(check_range(line, value, 0xFF, "byte")? as u8);
+                }
+            }
+            Statement::Words(values) => {
+                for value in values {
+                    let value = evaluate(line, value, &symbols)?;
+                    rom.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Statement::Instruction(mnemonic, operands) => {
+                let operands = operands
+                    .iter()
+                    .map(|operand| parse_operand(line, operand, &symbols))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for word in encode(line, mnemonic, &operands)? {
+                    rom.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+        }
+    }
+    Ok(rom)
+}
+
+/// F000 NNNN, written as `LD I, LONG addr`, is the only two-word instruction.
+fn instruction_len(operands: &[&str]) -> usize {
+    let is_long = operands
+        .iter()
+        .any(|operand| long_operand(operand).is_some());
+    if is_long {
+        4
+    } else {
+        2
+    }
+}
+
+fn long_operand(operand: &str) -> Option<&str> {
+    let (keyword, address) = operand.split_once(char::is_whitespace)?;
+    keyword
+        .eq_ignore_ascii_case("long")
+        .then_some(address.trim())
+}
+
+fn split_operands(operands: &str) -> Vec<&str> {
+    operands
+        .split(',')
+        .map(str::trim)
+        .filter(|operand| !operand.is_empty())
+        .collect()
+}
+
+fn check_symbol_name(line: &SourceLine, name: &str) -> Result<(), AssemblerError> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && name
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || character == '_')
+        && register(name).is_none()
+        && !KEYWORDS.contains(&name.to_ascii_lowercase().as_str());
+    if valid {
+        Ok(())
+    } else {
+        Err(line.error(format!("invalid name {name:?}")))
+    }
+}
+
+fn register(operand: &str) -> Option<u8> {
+    let mut characters = operand.chars();
+    match (characters.next(), characters.next(), characters.next()) {
+        (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|digit| digit as u8),
+        _ => None,
+    }
+}
+
+fn parse_operand(
+    line: &SourceLine,
+    operand: &str,
+    symbols: &HashMap<String, u16>,
+) -> Result<Operand, AssemblerError> {
+    if let Some(register) = register(operand) {
+        return Ok(Operand::Register(register));
+    }
+    if let Some(address) = long_operand(operand) {
+        return Ok(Operand::Long(evaluate(line, address, symbols)?));
+    }
+    let operand = match operand.to_ascii_lowercase().as_str() {
+        "i" => Operand::I,
+        "[i]" => Operand::IndirectI,
+        "dt" => Operand::DelayTimer,
+        "st" => Operand::SoundTimer,
+        "k" => Operand::Key,
+        "f" => Operand::Font,
+        "hf" => Operand::BigFont,
+        "b" => Operand::Bcd,
+        "r" => Operand::Rpl,
+        _ => Operand::Value(evaluate(line, operand, symbols)?),
+    };
+    Ok(operand)
+}
+
+/// Evaluates sums and differences of numbers, labels and constants.
+fn evaluate(
+    line: &SourceLine,
+    expression: &str,
+    symbols: &HashMap<String, u16>,
+) -> Result<u16, AssemblerError> {
+    let mut total: i64 = 0;
+    let mut sign = 1;
+    let mut term = String::new();
+    for character in expression.chars().chain(std::iter::once('+')) {
+        match character {
+            '+' | '-' => {
+                let term_text = term.trim();
+                if term_text.is_empty() {
+                    if character == '-' {
+                        sign = -sign;
+                    }
+                    continue;
+                }
+                total += sign * evaluate_term(line, term_text, symbols)? as i64;
+                sign = if character == '-' { -1 } else { 1 };
+                term.clear();
+            }
+            _ => term.push(character),
+        }
+    }
+    u16::try_from(total).map_err(|_| line.error(format!("{expression} is out of range")))
+}
+
+fn evaluate_term(
+    line: &SourceLine,
+    term: &str,
+    symbols: &HashMap<String, u16>,
+) -> Result<u16, AssemblerError> {
+    let lower = term.to_ascii_lowercase();
+    let number = if let Some(hex) = lower.strip_prefix("0x").or(lower.strip_prefix('#')) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u16::from_str_radix(binary, 2).ok()
+    } else if lower.starts_with(|character: char| character.is_ascii_digit()) {
+        lower.parse().ok()
+    } else {
+        let value = symbols.get(&lower).copied();
+        return value.ok_or_else(|| line.error(format!("unknown name {term}")));
+    };
+    number.ok_or_else(|| line.error(format!("invalid number {term}")))
+}
+
+fn check_range(line: &SourceLine, value: u16, max: u16, kind: &str) -> Result<u16, AssemblerError> {
+    if value > max {
+        return Err(line.error(format!("{value:#X} doesn't fit in a {kind}")));
+    }
+    Ok(value)
+}
+
+fn encode(
+    line: &SourceLine,
+    mnemonic: &str,
+    operands: &[Operand],
+) -> Result<Vec<u16>, AssemblerError> {
+    use Operand::*;
+
+    let address = |value: u16| check_range(line, value, 0xFFF, "12-bit address");
+    let byte = |value: u16| check_range(line, value, 0xFF, "byte");
+    let nibble = |value: u16| check_range(line, value, 0xF, "nibble");
+    let xy = |base: u16, x: u8, y: u8| base | (x as u16) << 8 | (y as u16) << 4;
+    let x = |base: u16, x: u8| base | (x as u16) << 8;
+
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let op = match (mnemonic.as_str(), operands) {
+        ("NOP", []) => 0x0000,
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SCD", [Value(n)]) => 0x00C0 | nibble(*n)?,
+        ("SCU", [Value(n)]) => 0x00D0 | nibble(*n)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("JP", [Value(nnn)]) => 0x1000 | address(*nnn)?,
+        ("JP", [Register(0), Value(nnn)]) => 0xB000 | address(*nnn)?,
+        ("CALL", [Value(nnn)]) => 0x2000 | address(*nnn)?,
+        ("SE", [Register(vx), Value(nn)]) => x(0x3000, *vx) | byte(*nn)?,
+        ("SNE", [Register(vx), Value(nn)]) => x(0x4000, *vx) | byte(*nn)?,
+        ("SE", [Register(vx), Register(vy)]) => xy(0x5000, *vx, *vy),
+        ("SAVE", [Register(vx), Register(vy)]) => xy(0x5002, *vx, *vy),
+        ("LOAD", [Register(vx), Register(vy)]) => xy(0x5003, *vx, *vy),
+        ("LD", [Register(vx), Value(nn)]) => x(0x6000, *vx) | byte(*nn)?,
+        ("ADD", [Register(vx), Value(nn)]) => x(0x7000, *vx) | byte(*nn)?,
+        ("LD", [Register(vx), Register(vy)]) => xy(0x8000, *vx, *vy),
+        ("OR", [Register(vx), Register(vy)]) => xy(0x8001, *vx, *vy),
+        ("AND", [Register(vx), Register(vy)]) => xy(0x8002, *vx, *vy),
+        ("XOR", [Register(vx), Register(vy)]) => xy(0x8003, *vx, *vy),
+        ("ADD", [Register(vx), Register(vy)]) => xy(0x8004, *vx, *vy),
+        ("SUB", [Register(vx), Register(vy)]) => xy(0x8005, *vx, *vy),
+        ("SHR", [Register(vx)]) => xy(0x8006, *vx, *vx),
+        ("SHR", [Register(vx), Register(vy)]) => xy(0x8006, *vx, *vy),
+        ("SUBN", [Register(vx), Register(vy)]) => xy(0x8007, *vx, *vy),
+        ("SHL", [Register(vx)]) => xy(0x800E, *vx, *vx),
+        ("SHL", [Register(vx), Register(vy)]) => xy(0x800E, *vx, *vy),
+        ("SNE", [Register(vx), Register(vy)]) => xy(0x9000, *vx, *vy),
+        ("LD", [I, Value(nnn)]) => 0xA000 | address(*nnn)?,
+        ("RND", [Register(vx), Value(nn)]) => x(0xC000, *vx) | byte(*nn)?,
+        ("DRW", [Register(vx), Register(vy), Value(n)]) => xy(0xD000, *vx, *vy) | nibble(*n)?,
+        ("SKP", [Register(vx)]) => x(0xE09E, *vx),
+        ("SKNP", [Register(vx)]) => x(0xE0A1, *vx),
+        ("LD", [I, Long(nnnn)]) => return Ok(vec![0xF000, *nnnn]),
+        ("PLANE", [Value(n)]) => x(0xF001, check_range(line, *n, 0x3, "plane mask")? as u8),
+        ("AUDIO", []) => 0xF002,
+        ("LD", [Register(vx), DelayTimer]) => x(0xF007, *vx),
+        ("LD", [Register(vx), Key]) => x(0xF00A, *vx),
+        ("LD", [DelayTimer, Register(vx)]) => x(0xF015, *vx),
+        ("LD", [SoundTimer, Register(vx)]) => x(0xF018, *vx),
+        ("ADD", [I, Register(vx)]) => x(0xF01E, *vx),
+        ("LD", [Font, Register(vx)]) => x(0xF029, *vx),
+        ("LD", [BigFont, Register(vx)]) => x(0xF030, *vx),
+        ("LD", [Bcd, Register(vx)]) => x(0xF033, *vx),
+        ("PITCH", [Register(vx)]) => x(0xF03A, *vx),
+        ("LD", [IndirectI, Register(vx)]) => x(0xF055, *vx),
+        ("LD", [Register(vx), IndirectI]) => x(0xF065, *vx),
+        ("LD", [Rpl, Register(vx)]) => x(0xF075, *vx),
+        ("LD", [Register(vx), Rpl]) => x(0xF085, *vx),
+        _ => {
+            return Err(line.error(format!(
+                "invalid instruction {mnemonic} with {} operand(s)",
+                operands.len()
+            )))
+        }
+    };
+    Ok(vec![op])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler;
+
+    fn error(source: &str) -> AssemblerError {
+        assemble(source).unwrap_err()
+    }
+
+    /// A fresh directory for tests that read source files.
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "toy-chip-8-assembler-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn labels_can_be_used_before_they_are_defined() {
+        let rom = assemble(
+            "start: JP end\n\
+             CALL sub\n\
+             sub: RET\n\
+             end: JP start",
+        )
+        .unwrap();
+        assert_eq!(rom, [0x12, 0x06, 0x22, 0x04, 0x00, 0xEE, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn constants_and_expressions() {
+        let rom = assemble(
+            "X = 4\n\
+             Y = X + 2 - 1\n\
+             LD V0, X\n\
+             LD V1, Y + 0x10\n\
+             LD V2, -X + 8",
+        )
+        .unwrap();
+        assert_eq!(rom, [0x60, 0x04, 0x61, 0x15, 0x62, 0x04]);
+    }
+
+    #[test]
+    fn number_formats_and_data() {
+        let rom = assemble("db 0xF0, #90, 0b10010000, 144\ndw 0x1234, 258").unwrap();
+        assert_eq!(rom, [0xF0, 0x90, 0x90, 0x90, 0x12, 0x34, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn comments_and_case_are_ignored() {
+        let rom = assemble("; header\n  ld va, 0X1f ; comment\nCls").unwrap();
+        assert_eq!(rom, [0x6A, 0x1F, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn long_takes_two_words() {
+        let rom = assemble("LD I, LONG data\ndata: db 1").unwrap();
+        assert_eq!(rom, [0xF0, 0x00, 0x02, 0x04, 0x01]);
+    }
+
+    #[test]
+    fn long_reaches_past_12_bits() {
+        let rom = assemble("LD I, LONG 0xFFF0").unwrap();
+        assert_eq!(rom, [0xF0, 0x00, 0xFF, 0xF0]);
+        assert!(error("LD I, 0xFFF0").message.contains("12-bit address"));
+    }
+
+    #[test]
+    fn values_out_of_range() {
+        assert!(error("LD V0, 256")
+            .message
+            .contains("doesn't fit in a byte"));
+        assert!(error("JP 0x1000")
+            .message
+            .contains("doesn't fit in a 12-bit address"));
+        assert!(error("DRW V0, V1, 16")
+            .message
+            .contains("doesn't fit in a nibble"));
+        assert!(error("PLANE 4")
+            .message
+            .contains("doesn't fit in a plane mask"));
+        assert!(error("db 0 - 1").message.contains("out of range"));
+        assert!(error("dw 0x10000").message.contains("invalid number"));
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        let error = error("CLS\n\nFOO V0");
+        assert_eq!(error.line, 3);
+        assert_eq!(
+            error.to_string(),
+            "<source>:3: invalid instruction FOO with 1 operand(s)"
+        );
+    }
+
+    #[test]
+    fn bad_names() {
+        assert!(error("JP nowhere").message.contains("unknown name nowhere"));
+        assert!(error("a: CLS\nA: CLS").message.contains("already defined"));
+        assert!(error("V1 = 3").message.contains("invalid name"));
+        assert!(error("dt: CLS").message.contains("invalid name"));
+        assert!(error("1x = 3").message.contains("invalid name"));
+    }
+
+    #[test]
+    fn program_must_fit_in_memory() {
+        let source = "dw 0\n".repeat((MEMORY_SIZE - PROGRAM_START as usize) / 2);
+        assert!(assemble(&source).is_ok());
+        let source = source + "db 0";
+        assert!(error(&source).message.contains("doesn't fit in memory"));
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_including_file() {
+        let directory = test_directory("include");
+        fs::create_dir_all(directory.join("lib")).unwrap();
+        fs::write(
+            directory.join("main.s"),
+            "include \"lib/sprites.s\"\nJP sprite",
+        )
+        .unwrap();
+        fs::write(directory.join("lib/sprites.s"), "sprite: db 0xFF, 0x81").unwrap();
+        let rom = assemble_file(&directory.join("main.s")).unwrap();
+        assert_eq!(rom, [0xFF, 0x81, 0x12, 0x00]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn include_errors() {
+        let directory = test_directory("include-errors");
+        fs::write(directory.join("loop.s"), "include \"loop.s\"").unwrap();
+        let error = assemble_file(&directory.join("loop.s")).unwrap_err();
+        assert!(error.message.contains("nested too deeply"));
+
+        fs::write(directory.join("missing.s"), "CLS\ninclude \"nope.s\"").unwrap();
+        let error = assemble_file(&directory.join("missing.s")).unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("couldn't read"));
+
+        fs::write(directory.join("unquoted.s"), "include nope.s").unwrap();
+        let error = assemble_file(&directory.join("unquoted.s")).unwrap_err();
+        assert!(error.message.contains("must be quoted"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn disassembled_mnemonics_assemble_to_the_same_opcode() {
+        let opcodes = [
+            0x0000, 0x00E0, 0x00EE, 0x00C5, 0x00D3, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF, 0x1234,
+            0xB456, 0x2ABC, 0x3A12, 0x4B34, 0x5120, 0x5122, 0x5123, 0x6C56, 0x7D78, 0x8120, 0x8121,
+            0x8122, 0x8123, 0x8124, 0x8125, 0x8126, 0x8127, 0x812E, 0x9120, 0xA321, 0xC1FF, 0xD125,
+            0xE19E, 0xE1A1, 0xF201, 0xF002, 0xF107, 0xF10A, 0xF115, 0xF118, 0xF11E, 0xF129, 0xF130,
+            0xF133, 0xF13A, 0xF155, 0xF165, 0xF175, 0xF185,
+        ];
+        for op in opcodes {
+            let mnemonic = disassembler::mnemonic(op);
+            assert_eq!(
+                assemble(&mnemonic).unwrap(),
+                u16::to_be_bytes(op),
+                "{mnemonic}"
+            );
+        }
+        let long = [0xF0, 0x00, 0x12, 0x34];
+        assert_eq!(
+            assemble(&disassembler::mnemonic_at(&long, 0)).unwrap(),
+            long
+        );
+    }
+
+    #[test]
+    fn disassembly_follows_the_assembled_program() {
+        let rom = assemble(
+            "CALL draw\n\
+             loop: JP loop\n\
+             draw: LD I, sprite\n\
+             RET\n\
+             sprite: db 0b11000011",
+        )
+        .unwrap();
+        let listing = disassembler::disassemble(&rom);
+        assert!(listing.contains("CALL sub_204"), "{listing}");
+        assert!(
+            listing.contains("loc_202:\n0x202  12 02  JP loc_202"),
+            "{listing}"
+        );
+        assert!(listing.contains("LD I, 0x208"), "{listing}");
+        assert!(listing.contains("DB 0xC3  ; ##....##"), "{listing}");
+    }
+}
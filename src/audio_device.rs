@@ -0,0 +1,176 @@
+use sdl2::audio::{AudioCallback, AudioDeviceLockGuard, AudioSpecDesired};
+
+use crate::config::Waveform;
+
+const SAMPLE_RATE: i32 = 44100;
+// Fade in/out length, keeps the tone from clicking when it starts or stops
+const RAMP_SAMPLES: f32 = SAMPLE_RATE as f32 * 0.005;
+
+pub struct ToneGenerator {
+    waveform: Waveform,
+    phase: f32,
+    phase_increment: f32,
+    volume: f32,
+    gain: f32,
+    playing: bool,
+    muted: bool,
+    // XO-CHIP audio pattern, replaces the waveform once a rom loads one
+    pattern: Option<[u8; 16]>,
+    pattern_position: f32,
+    pattern_increment: f32,
+}
+
+impl ToneGenerator {
+    fn sample(&self) -> f32 {
+        if let Some(pattern) = &self.pattern {
+            let bit = self.pattern_position as usize;
+            return if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                1.0
+            } else {
+                -1.0
+            };
+        }
+        match self.waveform {
+            Waveform::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+            Waveform::Sine => (self.phase * std::f32::consts::TAU).sin(),
+        }
+    }
+}
+
+impl AudioCallback for ToneGenerator {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let target_gain = if self.playing && !self.muted {
+            1.0
+        } else {
+            0.0
+        };
+        let gain_step = 1.0 / RAMP_SAMPLES;
+
+        for x in out.iter_mut() {
+            if self.gain < target_gain {
+                self.gain = (self.gain + gain_step).min(target_gain);
+            } else if self.gain > target_gain {
+                self.gain = (self.gain - gain_step).max(target_gain);
+            }
+
+            *x = self.sample() * self.gain * self.volume;
+            self.phase = (self.phase + self.phase_increment) % 1.0;
+            self.pattern_position = (self.pattern_position + self.pattern_increment) % 128.0;
+        }
+    }
+}
+
+pub struct AudioDevice {
+    // Missing without a working audio device, the emulator then runs silently
+    device: Option<sdl2::audio::AudioDevice<ToneGenerator>>,
+    sample_rate: f32,
+    playing: bool,
+    pattern: Option<[u8; 16]>,
+    pattern_rate: f32,
+}
+
+impl AudioDevice {
+    pub fn new(
+        sdl_context: &sdl2::Sdl,
+        frequency: f32,
+        volume: f32,
+        waveform: Waveform,
+        muted: bool,
+    ) -> Self {
+        let device = Self::open(sdl_context, frequency, volume, waveform, muted)
+            .map_err(|error| eprintln!("Couldn't open an audio device, sound is off: {error}"))
+            .ok();
+        let sample_rate = device
+            .as_ref()
+            .map_or(SAMPLE_RATE as f32, |device| device.spec().freq as f32);
+        AudioDevice {
+            device,
+            sample_rate,
+            playing: false,
+            pattern: None,
+            pattern_rate: 0.0,
+        }
+    }
+
+    fn open(
+        sdl_context: &sdl2::Sdl,
+        frequency: f32,
+        volume: f32,
+        waveform: Waveform,
+        muted: bool,
+    ) -> Result<sdl2::audio::AudioDevice<ToneGenerator>, String> {
+        let audio_subsystem = sdl_context.audio()?;
+        let desired_spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| ToneGenerator {
+            waveform,
+            phase: 0.0,
+            phase_increment: frequency / spec.freq as f32,
+            volume: volume.clamp(0.0, 1.0),
+            gain: 0.0,
+            playing: false,
+            muted,
+            pattern: None,
+            pattern_position: 0.0,
+            pattern_increment: 0.0,
+        })?;
+        // The device keeps running and outputs silence while the tone is off, so
+        // starting and stopping the tone between frames doesn't leave gaps
+        device.resume();
+        Ok(device)
+    }
+
+    fn generator(&mut self) -> Option<AudioDeviceLockGuard<'_, ToneGenerator>> {
+        self.device.as_mut().map(|device| device.lock())
+    }
+
+    pub fn set_playing(&mut self, playing: bool) {
+        if self.playing != playing {
+            self.playing = playing;
+            if let Some(mut generator) = self.generator() {
+                generator.playing = playing;
+            }
+        }
+    }
+
+    /// Plays `pattern` as 128 one-bit samples at `rate` bits per second
+    /// instead of the configured waveform.
+    pub fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, rate: f32) {
+        let pattern = pattern.copied();
+        if self.pattern != pattern || self.pattern_rate != rate {
+            self.pattern = pattern;
+            self.pattern_rate = rate;
+            let pattern_increment = rate / self.sample_rate;
+            if let Some(mut generator) = self.generator() {
+                generator.pattern = pattern;
+                generator.pattern_increment = pattern_increment;
+            }
+        }
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        if let Some(mut generator) = self.generator() {
+            generator.volume = volume.clamp(0.0, 1.0);
+        }
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        if let Some(mut generator) = self.generator() {
+            generator.muted = muted;
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::Parser;
+use toy_chip_8::chip8::Chip8;
+use toy_chip_8::config::HeadlessConfig;
+
+const DEFAULT_FRAMES: u32 = 600;
+
+// Indexed by the plane bits of a pixel
+const PIXEL_CHARACTERS: [char; 4] = ['.', '#', '+', '@'];
+
+const EXIT_MISMATCH: u8 = 1;
+const EXIT_FAULT: u8 = 2;
+
+fn main() -> ExitCode {
+    let config = HeadlessConfig::parse();
+
+    let rom_data = match fs::read(&config.rom_path) {
+        Ok(rom_data) => rom_data,
+        Err(error) => {
+            eprintln!("Couldn't read rom file {}: {error}", config.rom_path);
+            return ExitCode::from(EXIT_FAULT);
+        }
+    };
+
+    let mut chip8 = Chip8::new(config.quirks.quirks(), config.seed);
+    chip8.set_timing_model(config.timing.timing_model(config.speed));
+    if let Err(error) = chip8.load_rom(&rom_data) {
+        eprintln!("{error}");
+        return ExitCode::from(EXIT_FAULT);
+    }
+
+    if let Err(error) = run(&mut chip8, &config) {
+        eprintln!("The emulator stopped: {error}");
+        return ExitCode::from(EXIT_FAULT);
+    }
+
+    let screen = vram_to_ascii(&chip8.vram, chip8.display_width());
+    match &config.output {
+        Some(output) => {
+            if let Err(error) = fs::write(output, &screen) {
+                eprintln!("Couldn't write {output}: {error}");
+                return ExitCode::from(EXIT_FAULT);
+            }
+        }
+        None => print!("{screen}"),
+    }
+
+    if let Some(reference) = &config.reference {
+        let expected = match fs::read_to_string(reference) {
+            Ok(expected) => expected,
+            Err(error) => {
+                eprintln!("Couldn't read reference {reference}: {error}");
+                return ExitCode::from(EXIT_FAULT);
+            }
+        };
+        if !compare_screens(&expected, &screen) {
+            return ExitCode::from(EXIT_MISMATCH);
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn run(chip8: &mut Chip8, config: &HeadlessConfig) -> Result<(), toy_chip_8::Chip8Error> {
+    match config.cycles {
+        Some(max_cycles) => {
+            let mut cycles = 0;
+            while cycles < max_cycles && !chip8.has_exited() {
+                let (_, tick_result) = chip8.step(Duration::MAX)?;
+                // A waiting FX0A runs again and again without finishing, and
+                // nothing presses keys here
+                if chip8.is_waiting_for_key() {
+                    break;
+                }
+                if tick_result.executed {
+                    cycles += 1;
+                }
+            }
+        }
+        None => {
+            for _ in 0..config.frames.unwrap_or(DEFAULT_FRAMES) {
+                if chip8.has_exited() {
+                    break;
+                }
+                chip8.run_frame()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn vram_to_ascii(vram: &[u8], width: usize) -> String {
+    let mut screen = String::with_capacity(vram.len() + vram.len() / width);
+    for row in vram.chunks(width) {
+        screen.extend(
+            row.iter()
+                .map(|pixel| PIXEL_CHARACTERS[*pixel as usize & 0x3]),
+        );
+        screen.push('\n');
+    }
+    screen
+}
+
+fn compare_screens(expected: &str, actual: &str) -> bool {
+    let expected_rows: Vec<&str> = expected.lines().collect();
+    let actual_rows: Vec<&str> = actual.lines().collect();
+    if expected_rows == actual_rows {
+        return true;
+    }
+
+    eprintln!("Screen doesn't match the reference:");
+    for row in 0..expected_rows.len().max(actual_rows.len()) {
+        let expected_row = expected_rows.get(row).copied().unwrap_or("");
+        let actual_row = actual_rows.get(row).copied().unwrap_or("");
+        if expected_row != actual_row {
+            eprintln!("row {row:2} expected {expected_row}");
+            eprintln!("       actual   {actual_row}");
+        }
+    }
+    false
+}
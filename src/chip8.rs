@@ -1,8 +1,15 @@
+use std::fmt;
 use std::time::Duration;
 
-use rand::{prelude::ThreadRng, Rng};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
-const FONT: [u8; 80] = [
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use crate::save_state::{StateReader, StateWriter};
+use crate::timing::TimingModel;
+
+pub const FONT: [u8; 80] = [
     //0
     0b1111_0000,
     0b1001_0000,
@@ -101,9 +108,197 @@ const FONT: [u8; 80] = [
     0b1000_0000,
 ];
 
-const MEMORY_SIZE: usize = 4096;
+const BIG_FONT: [u8; 160] = [
+    //0
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    0b1111_1111,
+    0b1111_1111,
+    //1
+    0b0001_1000,
+    0b0111_1000,
+    0b0111_1000,
+    0b0001_1000,
+    0b0001_1000,
+    0b0001_1000,
+    0b0001_1000,
+    0b0001_1000,
+    0b1111_1111,
+    0b1111_1111,
+    //2
+    0b1111_1111,
+    0b1111_1111,
+    0b0000_0011,
+    0b0000_0011,
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0000,
+    0b1100_0000,
+    0b1111_1111,
+    0b1111_1111,
+    //3
+    0b1111_1111,
+    0b1111_1111,
+    0b0000_0011,
+    0b0000_0011,
+    0b1111_1111,
+    0b1111_1111,
+    0b0000_0011,
+    0b0000_0011,
+    0b1111_1111,
+    0b1111_1111,
+    //4
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    0b1111_1111,
+    0b1111_1111,
+    0b0000_0011,
+    0b0000_0011,
+    0b0000_0011,
+    0b0000_0011,
+    //5
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0000,
+    0b1100_0000,
+    0b1111_1111,
+    0b1111_1111,
+    0b0000_0011,
+    0b0000_0011,
+    0b1111_1111,
+    0b1111_1111,
+    //6
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0000,
+    0b1100_0000,
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0011,
+    0b1100_0011,
+    0b1111_1111,
+    0b1111_1111,
+    //7
+    0b1111_1111,
+    0b1111_1111,
+    0b0000_0011,
+    0b0000_0011,
+    0b0000_0110,
+    0b0000_1100,
+    0b0001_1000,
+    0b0001_1000,
+    0b0001_1000,
+    0b0001_1000,
+    //8
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0011,
+    0b1100_0011,
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0011,
+    0b1100_0011,
+    0b1111_1111,
+    0b1111_1111,
+    //9
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0011,
+    0b1100_0011,
+    0b1111_1111,
+    0b1111_1111,
+    0b0000_0011,
+    0b0000_0011,
+    0b1111_1111,
+    0b1111_1111,
+    //A
+    0b0111_1110,
+    0b1111_1111,
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    //B
+    0b1111_1100,
+    0b1111_1100,
+    0b1100_0011,
+    0b1100_0011,
+    0b1111_1100,
+    0b1111_1100,
+    0b1100_0011,
+    0b1100_0011,
+    0b1111_1100,
+    0b1111_1100,
+    //C
+    0b0011_1100,
+    0b1111_1111,
+    0b1100_0011,
+    0b1100_0000,
+    0b1100_0000,
+    0b1100_0000,
+    0b1100_0000,
+    0b1100_0011,
+    0b1111_1111,
+    0b0011_1100,
+    //D
+    0b1111_1100,
+    0b1111_1110,
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    0b1100_0011,
+    0b1111_1110,
+    0b1111_1100,
+    //E
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0000,
+    0b1100_0000,
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0000,
+    0b1100_0000,
+    0b1111_1111,
+    0b1111_1111,
+    //F
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0000,
+    0b1100_0000,
+    0b1111_1111,
+    0b1111_1111,
+    0b1100_0000,
+    0b1100_0000,
+    0b1100_0000,
+    0b1100_0000,
+];
+
+pub const MEMORY_SIZE: usize = 65536;
+pub const PROGRAM_START: u16 = 0x200;
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+const BIG_FONT_ADDRESS: usize = 0x50;
+/// XO-CHIP pitch that plays the audio pattern at 4000 bits per second.
+pub const DEFAULT_PITCH: u8 = 64;
+pub const FRAME_DURATION: Duration = Duration::from_micros(16666);
+const MIN_TICK: Duration = Duration::from_micros(1);
+#[derive(Clone)]
 pub struct Chip8 {
     ram: [u8; MEMORY_SIZE],
     pc: u16,
@@ -113,67 +308,429 @@ pub struct Chip8 {
     sp: u8,
     delay_timer: u8,
     sound_timer: u8,
-    pub vram: [bool; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+    /// Row-major pixels, `display_width() * display_height()` long. Bit 0
+    /// is the first plane and bit 1 the second XO-CHIP plane.
+    pub vram: Vec<u8>,
     vram_changed: bool,
+    hires: bool,
+    planes: u8,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    rpl: [u8; 16],
+    exited: bool,
     keys: [bool; 16],
+    // FX0A is waiting, the masks collect keys pressed and released since it started
+    waiting_for_key: bool,
+    key_presses: u16,
+    key_releases: u16,
     duration_until_next_execute: Duration,
     duration_until_next_timer_interrupt: Duration,
-    rng: ThreadRng,
+    rng: ChaCha8Rng,
+    quirks: Quirks,
+    timing_model: TimingModel,
 }
 
+#[derive(Default)]
 pub struct Chip8TickResult {
     pub vram_changed: bool,
     pub beep: bool,
+    pub executed: bool,
+    /// The hooks stopped the tick before the next instruction ran.
+    pub stopped: bool,
+}
+
+/// Lets tools such as the debugger observe execution from inside `tick`.
+pub trait Chip8Hooks {
+    /// Called before the instruction at `address` runs. Returning `false`
+    /// stops the tick without running it.
+    fn before_execute(&mut self, chip8: &Chip8, address: u16) -> bool;
+    /// Called after an instruction has run.
+    fn after_execute(&mut self, chip8: &Chip8);
+}
+
+struct NoHooks;
+
+impl Chip8Hooks for NoHooks {
+    fn before_execute(&mut self, _chip8: &Chip8, _address: u16) -> bool {
+        true
+    }
+
+    fn after_execute(&mut self, _chip8: &Chip8) {}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    UnknownOpcode { address: u16, opcode: u16 },
+    StackOverflow { address: u16 },
+    StackUnderflow { address: u16 },
+    MemoryOutOfBounds { address: u16, location: usize },
+    RomTooLarge { size: usize, capacity: usize },
+    UnreadableRom { path: String, reason: String },
+    InvalidSaveState { reason: &'static str },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { address, opcode } => {
+                write!(f, "unknown opcode {opcode:04X} at {address:03X}")
+            }
+            Chip8Error::StackOverflow { address } => {
+                write!(f, "stack overflow on CALL at {address:03X}")
+            }
+            Chip8Error::StackUnderflow { address } => {
+                write!(f, "stack underflow on RET at {address:03X}")
+            }
+            Chip8Error::MemoryOutOfBounds { address, location } => {
+                write!(
+                    f,
+                    "memory access out of range ({location:#X}) at {address:03X}"
+                )
+            }
+            Chip8Error::RomTooLarge { size, capacity } => {
+                write!(
+                    f,
+                    "rom is {size} bytes but only {capacity} bytes fit in memory"
+                )
+            }
+            Chip8Error::UnreadableRom { path, reason } => {
+                write!(f, "couldn't read rom {path}: {reason}")
+            }
+            Chip8Error::InvalidSaveState { reason } => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new(Quirks::default(), rand::random())
+    }
 }
 
 impl Chip8 {
-    pub fn new() -> Self {
+    pub fn new(quirks: Quirks, seed: u64) -> Self {
         let mut chip8 = Self {
             ram: [0; MEMORY_SIZE],
-            pc: 0x200,
+            pc: PROGRAM_START,
             v: [0; 16],
             i: 0,
             stack: [0; 16],
             sp: 0,
             delay_timer: 0,
             sound_timer: 0,
-            vram: [false; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            vram: vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
             vram_changed: false,
+            hires: false,
+            planes: 0x1,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            rpl: [0; 16],
+            exited: false,
             keys: [false; 16],
+            waiting_for_key: false,
+            key_presses: 0,
+            key_releases: 0,
             //Helpers
             duration_until_next_execute: Duration::ZERO,
             duration_until_next_timer_interrupt: Duration::ZERO,
-            rng: rand::thread_rng(),
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            quirks,
+            timing_model: TimingModel::default(),
         };
 
         //Load font
         chip8.ram[0..FONT.len()].copy_from_slice(&FONT);
+        chip8.ram[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
 
         chip8
     }
 
+    /// Puts the machine back into the state `new` creates, the rom has to be
+    /// loaded again. Quirks, timing model and held keys stay, `RND` starts
+    /// over from the same seed.
+    pub fn reset(&mut self) {
+        let mut chip8 = Self::new(self.quirks, 0);
+        chip8.rng = ChaCha8Rng::from_seed(self.rng.get_seed());
+        chip8.timing_model = self.timing_model;
+        chip8.keys = self.keys;
+        *self = chip8;
+    }
+
+    /// Resets everything but memory, so the rom and whatever it wrote stay.
+    pub fn soft_reset(&mut self) {
+        let ram = self.ram;
+        self.reset();
+        self.ram = ram;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn timing_model(&self) -> TimingModel {
+        self.timing_model
+    }
+
+    pub fn set_timing_model(&mut self, timing_model: TimingModel) {
+        self.timing_model = timing_model;
+    }
+
+    pub fn display_width(&self) -> usize {
+        if self.hires {
+            HIRES_DISPLAY_WIDTH
+        } else {
+            DISPLAY_WIDTH
+        }
+    }
+
+    pub fn display_height(&self) -> usize {
+        if self.hires {
+            HIRES_DISPLAY_HEIGHT
+        } else {
+            DISPLAY_HEIGHT
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Bitmask of the planes DXYN, CLS and the scroll instructions work on.
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// The pattern loaded with F002, `None` until the rom loads one.
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    /// Playback rate of the audio pattern in bits per second.
+    pub fn audio_pattern_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+    }
+
+    /// Whether the rom ran 00FD (EXIT).
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn set_v(&mut self, register: u8, value: u8) {
+        debug_assert!(register <= 0xF);
+        self.v[register as usize] = value;
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    pub fn ram(&self) -> &[u8; MEMORY_SIZE] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8; MEMORY_SIZE] {
+        &mut self.ram
+    }
+
+    pub fn stack(&self) -> &[u16; 16] {
+        &self.stack
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn keys(&self) -> &[bool; 16] {
+        &self.keys
+    }
+
+    /// Replaces the held keys without counting them as presses or releases,
+    /// so a waiting FX0A doesn't finish on a change the player never made.
+    pub fn set_keys(&mut self, keys: [bool; 16]) {
+        self.keys = keys;
+    }
+
+    /// Whether an FX0A started and is still waiting for a key.
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.waiting_for_key
+    }
+
     pub fn change_key_state(&mut self, key: u8, pressed: bool) {
         debug_assert!(key <= 0xF);
-        self.keys[key as usize] = pressed;
+        let was_pressed = std::mem::replace(&mut self.keys[key as usize], pressed);
+        match (was_pressed, pressed) {
+            (false, true) => self.key_presses |= 1 << key,
+            (true, false) => self.key_releases |= 1 << key,
+            _ => {}
+        }
     }
 
-    pub fn load_rom(&mut self, rom_data: &[u8]) {
-        if rom_data.len() > self.ram.len() - 0x200 {
-            panic!("Memory size is not enough for the rom");
+    pub fn load_rom(&mut self, rom_data: &[u8]) -> Result<(), Chip8Error> {
+        let capacity = self.ram.len() - PROGRAM_START as usize;
+        if rom_data.len() > capacity {
+            return Err(Chip8Error::RomTooLarge {
+                size: rom_data.len(),
+                capacity,
+            });
         }
-        self.ram[0x200..0x200 + rom_data.len()].copy_from_slice(rom_data);
+        let start = PROGRAM_START as usize;
+        self.ram[start..start + rom_data.len()].copy_from_slice(rom_data);
+        Ok(())
     }
 
-    pub fn tick(&mut self, delta_time: Duration) -> Chip8TickResult {
+    /// Serializes the whole machine state, quirks and timing model excluded.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(&self.ram);
+        writer.write_u16(self.pc);
+        writer.write_bytes(&self.v);
+        writer.write_u16(self.i);
+        for address in self.stack {
+            writer.write_u16(address);
+        }
+        writer.write_u8(self.sp);
+        writer.write_u8(self.delay_timer);
+        writer.write_u8(self.sound_timer);
+        writer.write_u8(self.hires as u8);
+        writer.write_bytes(&self.vram);
+        writer.write_u8(self.planes);
+        writer.write_u8(self.audio_pattern.is_some() as u8);
+        writer.write_bytes(&self.audio_pattern.unwrap_or_default());
+        writer.write_u8(self.pitch);
+        writer.write_bytes(&self.rpl);
+        writer.write_u8(self.exited as u8);
+        writer.write_bools(&self.keys);
+        writer.write_u8(self.waiting_for_key as u8);
+        writer.write_u16(self.key_presses);
+        writer.write_u16(self.key_releases);
+        writer.write_duration(self.duration_until_next_execute);
+        writer.write_duration(self.duration_until_next_timer_interrupt);
+        writer.write_bytes(&self.rng.get_seed());
+        writer.write_u64(self.rng.get_stream());
+        writer.write_u128(self.rng.get_word_pos());
+        writer.finish()
+    }
+
+    /// Restores a state produced by `save_state`. Leaves the machine untouched
+    /// if the data is invalid.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        let mut reader = StateReader::new(data)?;
+        let mut chip8 = self.clone();
+
+        chip8.ram = reader.read_array()?;
+        chip8.pc = reader.read_u16()?;
+        chip8.v = reader.read_array()?;
+        chip8.i = reader.read_u16()?;
+        for address in chip8.stack.iter_mut() {
+            *address = reader.read_u16()?;
+        }
+        chip8.sp = reader.read_u8()?;
+        chip8.delay_timer = reader.read_u8()?;
+        chip8.sound_timer = reader.read_u8()?;
+        chip8.hires = reader.read_u8()? != 0;
+        let vram_len = chip8.display_width() * chip8.display_height();
+        chip8.vram = reader.read_bytes(vram_len)?.to_vec();
+        chip8.planes = reader.read_u8()?;
+        let has_audio_pattern = reader.read_u8()? != 0;
+        let audio_pattern = reader.read_array()?;
+        chip8.audio_pattern = has_audio_pattern.then_some(audio_pattern);
+        chip8.pitch = reader.read_u8()?;
+        chip8.rpl = reader.read_array()?;
+        chip8.exited = reader.read_u8()? != 0;
+        reader.read_bools(&mut chip8.keys)?;
+        chip8.waiting_for_key = reader.read_u8()? != 0;
+        chip8.key_presses = reader.read_u16()?;
+        chip8.key_releases = reader.read_u16()?;
+        chip8.duration_until_next_execute = reader.read_duration()?;
+        chip8.duration_until_next_timer_interrupt = reader.read_duration()?;
+        chip8.rng = ChaCha8Rng::from_seed(reader.read_array()?);
+        chip8.rng.set_stream(reader.read_u64()?);
+        chip8.rng.set_word_pos(reader.read_u128()?);
+        reader.finish()?;
+
+        if chip8.sp as usize > chip8.stack.len() {
+            return Err(Chip8Error::InvalidSaveState {
+                reason: "stack pointer out of range",
+            });
+        }
+        chip8.vram_changed = false;
+        *self = chip8;
+        Ok(())
+    }
+
+    pub fn tick(&mut self, delta_time: Duration) -> Result<Chip8TickResult, Chip8Error> {
+        self.tick_with_hooks(delta_time, &mut NoHooks)
+    }
+
+    pub fn tick_with_hooks(
+        &mut self,
+        delta_time: Duration,
+        hooks: &mut impl Chip8Hooks,
+    ) -> Result<Chip8TickResult, Chip8Error> {
+        if self.exited {
+            return Ok(Chip8TickResult::default());
+        }
+
         let mut vram_changed = false;
         let mut beep = false;
+        let mut executed = false;
 
         self.duration_until_next_execute =
             self.duration_until_next_execute.saturating_sub(delta_time);
 
         if self.duration_until_next_execute.is_zero() {
-            let op = self.fetch();
-            self.decode_and_execute(op);
+            if !hooks.before_execute(self, self.pc) {
+                return Ok(Chip8TickResult {
+                    vram_changed,
+                    beep,
+                    executed,
+                    stopped: true,
+                });
+            }
+
+            let address = self.pc;
+            let op = self.fetch(address)?;
+            self.decode_and_execute(address, op)?;
+            executed = true;
+            hooks.after_execute(self);
 
             if self.vram_changed {
                 vram_changed = true;
@@ -186,7 +743,7 @@ impl Chip8 {
             .saturating_sub(delta_time);
 
         if self.duration_until_next_timer_interrupt.is_zero() {
-            self.duration_until_next_timer_interrupt = Duration::from_micros(16666);
+            self.duration_until_next_timer_interrupt = FRAME_DURATION;
             if self.delay_timer > 0 {
                 self.delay_timer -= 1;
             }
@@ -196,280 +753,1067 @@ impl Chip8 {
             }
         }
 
-        Chip8TickResult { vram_changed, beep }
+        Ok(Chip8TickResult {
+            vram_changed,
+            beep,
+            executed,
+            stopped: false,
+        })
+    }
+
+    /// Time until the next instruction or timer interrupt is due, useful for
+    /// driving `tick` with simulated time.
+    pub fn duration_until_next_event(&self) -> Duration {
+        self.duration_until_next_execute
+            .min(self.duration_until_next_timer_interrupt)
+    }
+
+    /// Runs one 60Hz frame worth of instructions, the timers count down once.
+    pub fn run_frame(&mut self) -> Result<Chip8TickResult, Chip8Error> {
+        self.run_frame_with_hooks(&mut NoHooks)
+    }
+
+    pub fn run_frame_with_hooks(
+        &mut self,
+        hooks: &mut impl Chip8Hooks,
+    ) -> Result<Chip8TickResult, Chip8Error> {
+        let mut frame_result = Chip8TickResult::default();
+        let mut remaining = FRAME_DURATION;
+        while !remaining.is_zero() && !self.exited {
+            let (delta_time, tick_result) = self.step_with_hooks(remaining, hooks)?;
+            remaining -= delta_time;
+            frame_result.vram_changed |= tick_result.vram_changed;
+            frame_result.beep |= tick_result.beep;
+            frame_result.executed |= tick_result.executed;
+            if tick_result.stopped {
+                frame_result.stopped = true;
+                break;
+            }
+        }
+        Ok(frame_result)
+    }
+
+    /// Runs simulated time forward to the next instruction or timer
+    /// interrupt, but no further than `max_duration`. Returns the time that
+    /// passed along with what happened.
+    pub fn step(
+        &mut self,
+        max_duration: Duration,
+    ) -> Result<(Duration, Chip8TickResult), Chip8Error> {
+        self.step_with_hooks(max_duration, &mut NoHooks)
+    }
+
+    fn step_with_hooks(
+        &mut self,
+        max_duration: Duration,
+        hooks: &mut impl Chip8Hooks,
+    ) -> Result<(Duration, Chip8TickResult), Chip8Error> {
+        // Zero-cost instructions like a waiting FX0A would otherwise never let time pass
+        let delta_time = self
+            .duration_until_next_event()
+            .max(MIN_TICK)
+            .min(max_duration);
+        Ok((delta_time, self.tick_with_hooks(delta_time, hooks)?))
     }
 
-    fn fetch(&mut self) -> u16 {
+    /// Reads the word at PC for the instruction at `address`.
+    fn fetch(&mut self, address: u16) -> Result<u16, Chip8Error> {
+        self.check_memory_range(address, self.pc as usize, 2)?;
         let first_byte = self.ram[self.pc as usize] as u16;
-        let second_byte = self.ram[(self.pc + 1) as usize] as u16;
-        self.pc += 2;
-        (first_byte << 8) | second_byte
+        let second_byte = self.ram[self.pc as usize + 1] as u16;
+        self.advance_pc(address, 2)?;
+        Ok((first_byte << 8) | second_byte)
     }
 
-    fn decode_and_execute(&mut self, op: u16) {
-        let first_nibble = ((op & 0xF000) >> 12) as u8;
-        let x = ((op & 0x0F00) >> 8) as u8;
-        let y = ((op & 0x00F0) >> 4) as u8;
-        let n = (op & 0x000F) as u8;
-        let nn = (op & 0x00FF) as u8;
-        let nnn = (op & 0x0FFF) as u16;
+    /// Moves PC forward, running off the end of memory is a fault rather than
+    /// wrapping around to 0.
+    fn advance_pc(&mut self, address: u16, len: u16) -> Result<(), Chip8Error> {
+        self.pc = self
+            .pc
+            .checked_add(len)
+            .ok_or(Chip8Error::MemoryOutOfBounds {
+                address,
+                location: self.pc as usize + len as usize,
+            })?;
+        Ok(())
+    }
 
-        match (first_nibble, x, y, n) {
+    fn check_memory_range(&self, address: u16, start: usize, len: usize) -> Result<(), Chip8Error> {
+        if start + len > self.ram.len() {
+            return Err(Chip8Error::MemoryOutOfBounds {
+                address,
+                location: start.max(self.ram.len()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Skips the next instruction, F000 NNNN is two words long.
+    fn skip_next_instruction(&mut self, address: u16) -> Result<(), Chip8Error> {
+        let pc = self.pc as usize;
+        let is_long_load =
+            pc + 1 < self.ram.len() && self.ram[pc] == 0xF0 && self.ram[pc + 1] == 0x00;
+        self.advance_pc(address, if is_long_load { 4 } else { 2 })
+    }
+
+    /// Registers from Vx to Vy in order, counting down if y is below x.
+    fn register_range(x: u8, y: u8) -> Vec<usize> {
+        let (x, y) = (x as usize, y as usize);
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.vram = vec![0; self.display_width() * self.display_height()];
+        self.vram_changed = true;
+    }
+
+    /// Moves the selected planes by `columns` to the right and `rows` down,
+    /// pixels scrolled in are blank.
+    fn scroll(&mut self, columns: isize, rows: isize) {
+        let width = self.display_width() as isize;
+        let height = self.display_height() as isize;
+        let previous = self.vram.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = (x - columns, y - rows);
+                let source = if (0..width).contains(&source_x) && (0..height).contains(&source_y) {
+                    previous[(source_y * width + source_x) as usize]
+                } else {
+                    0
+                };
+                let pixel = &mut self.vram[(y * width + x) as usize];
+                *pixel = (*pixel & !self.planes) | (source & self.planes);
+            }
+        }
+        self.vram_changed = true;
+    }
+
+    fn decode_and_execute(&mut self, address: u16, op: u16) -> Result<(), Chip8Error> {
+        let Some(instruction) = Instruction::decode(op) else {
+            return Err(Chip8Error::UnknownOpcode {
+                address,
+                opcode: op,
+            });
+        };
+
+        match instruction {
             // NOP
-            (0x0, 0x0, 0x0, 0x0) => {
-                self.duration_until_next_execute = Duration::ZERO;
+            Instruction::Nop => {
+                self.duration_until_next_execute = self.timing_model.instruction_duration(0);
             }
             // CLS
-            (0x0, 0x0, 0xE, 0x0) => {
-                self.vram.fill(false);
+            Instruction::Cls => {
+                for pixel in self.vram.iter_mut() {
+                    *pixel &= !self.planes;
+                }
                 self.vram_changed = true;
-                self.duration_until_next_execute = Duration::from_micros(109);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(109);
             }
             // RET
-            (0x0, 0x0, 0xE, 0xE) => {
+            Instruction::Ret => {
+                if self.sp == 0 {
+                    return Err(Chip8Error::StackUnderflow { address });
+                }
                 self.sp -= 1;
                 self.pc = self.stack[self.sp as usize];
-                self.duration_until_next_execute = Duration::from_micros(105);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(105);
+            }
+            // SCD nibble
+            Instruction::Scd(n) => {
+                self.scroll(0, n as isize);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
+            }
+            // SCU nibble
+            Instruction::Scu(n) => {
+                self.scroll(0, -(n as isize));
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
+            }
+            // SCR
+            Instruction::Scr => {
+                self.scroll(4, 0);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
+            }
+            // SCL
+            Instruction::Scl => {
+                self.scroll(-4, 0);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
+            }
+            // EXIT
+            Instruction::Exit => {
+                self.exited = true;
+                self.duration_until_next_execute = self.timing_model.instruction_duration(0);
+            }
+            // LOW
+            Instruction::Low => {
+                self.set_hires(false);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(109);
+            }
+            // HIGH
+            Instruction::High => {
+                self.set_hires(true);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(109);
             }
             // JP addr
-            (0x1, _, _, _) => {
+            Instruction::Jp(nnn) => {
                 self.pc = nnn;
-                self.duration_until_next_execute = Duration::from_micros(105);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(105);
             }
             // CALL addr
-            (0x2, _, _, _) => {
+            Instruction::Call(nnn) => {
+                if self.sp as usize == self.stack.len() {
+                    return Err(Chip8Error::StackOverflow { address });
+                }
                 self.stack[self.sp as usize] = self.pc;
                 self.sp += 1;
                 self.pc = nnn;
-                self.duration_until_next_execute = Duration::from_micros(105);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(105);
             }
             // SE Vx, byte
-            (0x3, _, _, _) => {
+            Instruction::SeByte(x, nn) => {
                 if self.v[x as usize] == nn {
-                    self.pc += 2;
+                    self.skip_next_instruction(address)?;
                 }
-                self.duration_until_next_execute = Duration::from_micros(55);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(55);
             }
             // SNE Vx, byte
-            (0x4, _, _, _) => {
+            Instruction::SneByte(x, nn) => {
                 if self.v[x as usize] != nn {
-                    self.pc += 2;
+                    self.skip_next_instruction(address)?;
                 }
-                self.duration_until_next_execute = Duration::from_micros(55);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(55);
             }
             // SE Vx, Vy
-            (0x5, _, _, 0x0) => {
+            Instruction::SeReg(x, y) => {
                 if self.v[x as usize] == self.v[y as usize] {
-                    self.pc += 2;
+                    self.skip_next_instruction(address)?;
+                }
+                self.duration_until_next_execute = self.timing_model.instruction_duration(73);
+            }
+            // SAVE Vx, Vy
+            Instruction::SaveRange(x, y) => {
+                let count = x.abs_diff(y) as usize + 1;
+                self.check_memory_range(address, self.i as usize, count)?;
+                for (offset, register) in Self::register_range(x, y).into_iter().enumerate() {
+                    self.ram[self.i as usize + offset] = self.v[register];
                 }
-                self.duration_until_next_execute = Duration::from_micros(73);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(605);
+            }
+            // LOAD Vx, Vy
+            Instruction::LoadRange(x, y) => {
+                let count = x.abs_diff(y) as usize + 1;
+                self.check_memory_range(address, self.i as usize, count)?;
+                for (offset, register) in Self::register_range(x, y).into_iter().enumerate() {
+                    self.v[register] = self.ram[self.i as usize + offset];
+                }
+                self.duration_until_next_execute = self.timing_model.instruction_duration(605);
             }
             // LD Vx, byte
-            (0x6, _, _, _) => {
+            Instruction::LdByte(x, nn) => {
                 self.v[x as usize] = nn;
-                self.duration_until_next_execute = Duration::from_micros(27);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(27);
             }
             // ADD Vx, byte
-            (0x7, _, _, _) => {
+            Instruction::AddByte(x, nn) => {
                 self.v[x as usize] = self.v[x as usize].wrapping_add(nn);
-                self.duration_until_next_execute = Duration::from_micros(45);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(45);
             }
             // LD Vx, Vy
-            (0x8, _, _, 0x0) => {
+            Instruction::LdReg(x, y) => {
                 self.v[x as usize] = self.v[y as usize];
-                self.duration_until_next_execute = Duration::from_micros(200);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
             }
             // OR Vx, Vy
-            (0x8, _, _, 0x1) => {
+            Instruction::Or(x, y) => {
                 self.v[x as usize] |= self.v[y as usize];
-                self.v[0xF] = 0x0;
-                self.duration_until_next_execute = Duration::from_micros(200);
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0x0;
+                }
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
             }
             // AND Vx, Vy
-            (0x8, _, _, 0x2) => {
+            Instruction::And(x, y) => {
                 self.v[x as usize] &= self.v[y as usize];
-                self.v[0xF] = 0x0;
-                self.duration_until_next_execute = Duration::from_micros(200);
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0x0;
+                }
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
             }
             // XOR Vx, Vy
-            (0x8, _, _, 0x3) => {
+            Instruction::Xor(x, y) => {
                 self.v[x as usize] ^= self.v[y as usize];
-                self.v[0xF] = 0x0;
-                self.duration_until_next_execute = Duration::from_micros(200);
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0x0;
+                }
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
             }
             // ADD Vx, Vy
-            (0x8, _, _, 0x4) => {
+            Instruction::AddReg(x, y) => {
                 let (sum, carry) = self.v[x as usize].overflowing_add(self.v[y as usize]);
                 self.v[x as usize] = sum;
                 self.v[0xF] = if carry { 0x1 } else { 0x0 };
-                self.duration_until_next_execute = Duration::from_micros(200);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
             }
             // SUB Vx, Vy
-            (0x8, _, _, 0x5) => {
+            Instruction::Sub(x, y) => {
                 let (diff, borrow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
                 self.v[x as usize] = diff;
                 self.v[0xF] = if !borrow { 0x1 } else { 0x0 };
-                self.duration_until_next_execute = Duration::from_micros(200);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
             }
             // SHR Vx {, Vy}
-            (0x8, _, _, 0x6) => {
-                let vy = self.v[y as usize];
-                self.v[x as usize] = vy >> 1;
-                self.v[0xF] = vy & 0x1;
-                self.duration_until_next_execute = Duration::from_micros(200);
+            Instruction::Shr(x, y) => {
+                let source = if self.quirks.shift_uses_vy { y } else { x };
+                let value = self.v[source as usize];
+                self.v[x as usize] = value >> 1;
+                self.v[0xF] = value & 0x1;
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
             }
             // SUBN Vx, Vy
-            (0x8, _, _, 0x7) => {
+            Instruction::Subn(x, y) => {
                 let (diff, borrow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
                 self.v[x as usize] = diff;
                 self.v[0xF] = if !borrow { 0x1 } else { 0x0 };
-                self.duration_until_next_execute = Duration::from_micros(200);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
             }
             // SHL Vx {, Vy}
-            (0x8, _, _, 0xE) => {
-                let vy = self.v[y as usize];
-                self.v[x as usize] = vy << 1;
-                self.v[0xF] = (vy & 0x80) >> 7;
-                self.duration_until_next_execute = Duration::from_micros(200);
+            Instruction::Shl(x, y) => {
+                let source = if self.quirks.shift_uses_vy { y } else { x };
+                let value = self.v[source as usize];
+                self.v[x as usize] = value << 1;
+                self.v[0xF] = (value & 0x80) >> 7;
+                self.duration_until_next_execute = self.timing_model.instruction_duration(200);
             }
             // SNE Vx, Vy
-            (0x9, _, _, 0x0) => {
+            Instruction::SneReg(x, y) => {
                 if self.v[x as usize] != self.v[y as usize] {
-                    self.pc += 2;
+                    self.skip_next_instruction(address)?;
                 }
-                self.duration_until_next_execute = Duration::from_micros(73);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(73);
             }
             // LD I, addr
-            (0xA, _, _, _) => {
+            Instruction::LdI(nnn) => {
                 self.i = nnn;
-                self.duration_until_next_execute = Duration::from_micros(55);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(55);
             }
             // JP V0, addr
-            (0xB, _, _, _) => {
-                self.pc = nnn + self.v[0x0] as u16;
-                self.duration_until_next_execute = Duration::from_micros(105);
+            Instruction::JpV0(nnn) => {
+                let offset_register = if self.quirks.jump_uses_vx {
+                    nnn >> 8
+                } else {
+                    0x0
+                };
+                self.pc = nnn + self.v[offset_register as usize] as u16;
+                self.duration_until_next_execute = self.timing_model.instruction_duration(105);
             }
             // RND Vx, byte
-            (0xC, _, _, _) => {
+            Instruction::Rnd(x, nn) => {
                 self.v[x as usize] = self.rng.gen::<u8>() & nn;
-                self.duration_until_next_execute = Duration::from_micros(164);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(164);
             }
             // DRW Vx, Vy, nibble
-            (0xD, _, _, _) => {
-                let mut y_coord = self.v[y as usize] as usize % DISPLAY_HEIGHT;
+            Instruction::Drw(x, y, n) => {
+                let width = self.display_width();
+                let height = self.display_height();
+                // DXY0 draws a 16x16 sprite made of two bytes per row
+                let (sprite_width, sprite_height) = if n == 0 { (16, 16) } else { (8, n as usize) };
+                let bytes_per_row = sprite_width / 8;
+                let sprite_len = sprite_height * bytes_per_row;
+                let start_x = self.v[x as usize] as usize % width;
+                let start_y = self.v[y as usize] as usize % height;
+                let plane_count = self.planes.count_ones() as usize;
+                self.check_memory_range(address, self.i as usize, sprite_len * plane_count)?;
                 self.v[0xF] = 0x0;
-                for row in 0..n as usize {
-                    if y_coord == DISPLAY_HEIGHT {
-                        break;
-                    }
+                // Each selected plane takes the next sprite from memory
+                let selected_planes = [0x1, 0x2]
+                    .into_iter()
+                    .filter(|plane| self.planes & plane != 0);
+                for (index, plane) in selected_planes.enumerate() {
+                    let sprite_start = self.i as usize + index * sprite_len;
+                    for row in 0..sprite_height {
+                        let mut y_coord = start_y + row;
+                        if y_coord >= height {
+                            if !self.quirks.sprites_wrap {
+                                break;
+                            }
+                            y_coord %= height;
+                        }
 
-                    let mut x_coord = self.v[x as usize] as usize % DISPLAY_WIDTH;
-                    let row_data = self.ram[self.i as usize + row];
+                        let row_start = sprite_start + row * bytes_per_row;
+                        let row_data = self.ram[row_start..row_start + bytes_per_row]
+                            .iter()
+                            .fold(0u16, |data, byte| (data << 8) | *byte as u16);
 
-                    for pixel in 0..8usize {
-                        if x_coord == DISPLAY_WIDTH {
-                            break;
-                        }
+                        for pixel in 0..sprite_width {
+                            let mut x_coord = start_x + pixel;
+                            if x_coord >= width {
+                                if !self.quirks.sprites_wrap {
+                                    break;
+                                }
+                                x_coord %= width;
+                            }
 
-                        let sprite_pixel = ((row_data >> (7 - pixel)) & 0x1) == 0x1;
-                        let display_pixel = self.vram[y_coord * DISPLAY_WIDTH + x_coord];
+                            let sprite_pixel =
+                                ((row_data >> (sprite_width - 1 - pixel)) & 0x1) == 0x1;
+                            let display_pixel = &mut self.vram[y_coord * width + x_coord];
 
-                        if sprite_pixel {
-                            if display_pixel {
-                                self.v[0xF] = 0x1;
+                            if sprite_pixel {
+                                if *display_pixel & plane != 0 {
+                                    self.v[0xF] = 0x1;
+                                }
+                                *display_pixel ^= plane;
+                                self.vram_changed = true;
                             }
-                            self.vram[y_coord * DISPLAY_WIDTH + x_coord] = !display_pixel;
-                            self.vram_changed = true;
                         }
-                        x_coord += 1;
                     }
-                    y_coord += 1;
                 }
-                self.duration_until_next_execute = Duration::from_micros(22734);
+                self.duration_until_next_execute = if self.quirks.display_wait {
+                    // Wait for the vertical blank interrupt like the VIP does
+                    self.duration_until_next_timer_interrupt
+                } else {
+                    // Roughly what a sprite takes on the VIP, just not synced to the interrupt
+                    self.timing_model.instruction_duration(22734)
+                };
             }
             // SKP Vx
-            (0xE, _, 0x9, 0xE) => {
-                if self.keys[self.v[x as usize] as usize] {
-                    self.pc += 2;
+            Instruction::Skp(x) => {
+                if self.keys[(self.v[x as usize] & 0xF) as usize] {
+                    self.skip_next_instruction(address)?;
                 }
-                self.duration_until_next_execute = Duration::from_micros(73);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(73);
             }
             // SKNP Vx
-            (0xE, _, 0xA, 0x1) => {
-                if !self.keys[self.v[x as usize] as usize] {
-                    self.pc += 2;
+            Instruction::Sknp(x) => {
+                if !self.keys[(self.v[x as usize] & 0xF) as usize] {
+                    self.skip_next_instruction(address)?;
                 }
-                self.duration_until_next_execute = Duration::from_micros(73);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(73);
+            }
+            // LD I, LONG addr
+            Instruction::LdILong => {
+                self.i = self.fetch(address)?;
+                self.duration_until_next_execute = self.timing_model.instruction_duration(110);
+            }
+            // PLANE n
+            Instruction::Plane(n) => {
+                self.planes = n & 0x3;
+                self.duration_until_next_execute = self.timing_model.instruction_duration(45);
+            }
+            // AUDIO
+            Instruction::Audio => {
+                self.check_memory_range(address, self.i as usize, 16)?;
+                let start = self.i as usize;
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(&self.ram[start..start + 16]);
+                self.audio_pattern = Some(pattern);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(605);
             }
             // LD Vx, DT
-            (0xF, _, 0x0, 0x7) => {
+            Instruction::LdVxDt(x) => {
                 self.v[x as usize] = self.delay_timer;
-                self.duration_until_next_execute = Duration::from_micros(45);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(45);
             }
             // LD Vx, K
-            (0xF, _, 0x0, 0xA) => {
-                if let Some(key) = self.keys.iter().position(|pressed| *pressed) {
-                    self.v[x as usize] = key as u8;
+            Instruction::LdVxK(x) => {
+                // Only changes after the wait starts count, so a key already down
+                // has to be pressed again, or in release mode just let go
+                if !self.waiting_for_key {
+                    self.waiting_for_key = true;
+                    self.key_presses = 0;
+                    self.key_releases = 0;
+                }
+                let keys = if self.quirks.key_wait_on_press {
+                    self.key_presses
+                } else {
+                    self.key_releases
+                };
+                if keys != 0 {
+                    self.v[x as usize] = keys.trailing_zeros() as u8;
+                    self.waiting_for_key = false;
                 } else {
-                    self.pc -= 2;
+                    // Keep running FX0A, the timers count down meanwhile
+                    self.pc = address;
                 }
-                self.duration_until_next_execute = Duration::ZERO;
+                self.duration_until_next_execute = self.timing_model.instruction_duration(0);
             }
             // LD DT, Vx
-            (0xF, _, 0x1, 0x5) => {
+            Instruction::LdDtVx(x) => {
                 self.delay_timer = self.v[x as usize];
-                self.duration_until_next_execute = Duration::from_micros(45);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(45);
             }
             // LD ST, Vx
-            (0xF, _, 0x1, 0x8) => {
+            Instruction::LdStVx(x) => {
                 self.sound_timer = self.v[x as usize];
-                self.duration_until_next_execute = Duration::from_micros(45);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(45);
             }
             // ADD I, Vx
-            (0xF, _, 0x1, 0xE) => {
-                self.i += self.v[x as usize] as u16;
-                if self.i >= 0x1000 {
-                    self.v[0xF] = 0x1;
-                    self.i &= 0x0FFF;
+            Instruction::AddI(x) => {
+                let i = self.i.wrapping_add(self.v[x as usize] as u16);
+                // VF flags I leaving the original 4K address space
+                self.v[0xF] = if self.i < 0x1000 && i >= 0x1000 {
+                    0x1
                 } else {
-                    self.v[0xF] = 0x0;
-                }
-                self.duration_until_next_execute = Duration::from_micros(86);
+                    0x0
+                };
+                self.i = i;
+                self.duration_until_next_execute = self.timing_model.instruction_duration(86);
             }
             // LD F, Vx
-            (0xF, _, 0x2, 0x9) => {
+            Instruction::LdF(x) => {
                 self.i = (self.v[x as usize] & 0x0F) as u16 * 5;
-                self.duration_until_next_execute = Duration::from_micros(91);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(91);
+            }
+            // LD HF, Vx
+            Instruction::LdHf(x) => {
+                self.i = BIG_FONT_ADDRESS as u16 + (self.v[x as usize] & 0x0F) as u16 * 10;
+                self.duration_until_next_execute = self.timing_model.instruction_duration(91);
             }
             // LD B, Vx
-            (0xF, _, 0x3, 0x3) => {
+            Instruction::LdB(x) => {
+                self.check_memory_range(address, self.i as usize, 3)?;
                 let mut vx = self.v[x as usize];
                 for digit in (0..=2usize).rev() {
                     self.ram[self.i as usize + digit] = vx % 10;
                     vx /= 10;
                 }
-                self.duration_until_next_execute = Duration::from_micros(927);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(927);
+            }
+            // PITCH Vx
+            Instruction::Pitch(x) => {
+                self.pitch = self.v[x as usize];
+                self.duration_until_next_execute = self.timing_model.instruction_duration(45);
             }
             // LD [I], Vx
-            // TODO: consider making this instruction configurable
-            (0xF, _, 0x5, 0x5) => {
+            Instruction::LdMemVx(x) => {
+                self.check_memory_range(address, self.i as usize, x as usize + 1)?;
                 for i in 0..=x as usize {
-                    self.ram[self.i as usize] = self.v[i];
-                    self.i += 1;
+                    self.ram[self.i as usize + i] = self.v[i];
                 }
-                self.duration_until_next_execute = Duration::from_micros(605);
+                if self.quirks.memory_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+                self.duration_until_next_execute = self.timing_model.instruction_duration(605);
             }
             // LD Vx, [I]
-            // TODO: consider making this instruction configurable
-            (0xF, _, 0x6, 0x5) => {
+            Instruction::LdVxMem(x) => {
+                self.check_memory_range(address, self.i as usize, x as usize + 1)?;
                 for i in 0..=x as usize {
-                    self.v[i] = self.ram[self.i as usize];
-                    self.i += 1;
+                    self.v[i] = self.ram[self.i as usize + i];
+                }
+                if self.quirks.memory_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
                 }
-                self.duration_until_next_execute = Duration::from_micros(605);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(605);
+            }
+            // LD R, Vx
+            Instruction::LdRVx(x) => {
+                self.rpl[..=x as usize].copy_from_slice(&self.v[..=x as usize]);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(605);
+            }
+            // LD Vx, R
+            Instruction::LdVxR(x) => {
+                self.v[..=x as usize].copy_from_slice(&self.rpl[..=x as usize]);
+                self.duration_until_next_execute = self.timing_model.instruction_duration(605);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip8_with_rom(quirks: Quirks, rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new(quirks, 0);
+        chip8.load_rom(rom).unwrap();
+        chip8
+    }
+
+    /// Runs `rom` for `frames` frames, roms end by spinning on a jump.
+    fn run_rom(quirks: Quirks, rom: &[u8], frames: usize) -> Chip8 {
+        let mut chip8 = chip8_with_rom(quirks, rom);
+        for _ in 0..frames {
+            chip8.run_frame().unwrap();
+        }
+        chip8
+    }
+
+    /// Coordinates of the pixels set in any plane, in x then y order.
+    fn lit_pixels(chip8: &Chip8) -> Vec<(usize, usize)> {
+        let width = chip8.display_width();
+        let mut pixels: Vec<(usize, usize)> = chip8
+            .vram
+            .iter()
+            .enumerate()
+            .filter(|(_, pixel)| **pixel != 0)
+            .map(|(index, _)| (index % width, index / width))
+            .collect();
+        pixels.sort();
+        pixels
+    }
+
+    /// FX0A into V0, then spins once a key came.
+    const WAIT_FOR_KEY: [u8; 4] = [
+        0xF0, 0x0A, // LD V0, K
+        0x12, 0x02, // JP 0x202
+    ];
+
+    #[test]
+    fn key_wait_finishes_on_release_by_default() {
+        let mut chip8 = chip8_with_rom(Quirks::default(), &WAIT_FOR_KEY);
+        chip8.run_frame().unwrap();
+        chip8.change_key_state(0xA, true);
+        chip8.run_frame().unwrap();
+        assert!(chip8.is_waiting_for_key());
+        assert_eq!(chip8.pc(), PROGRAM_START);
+
+        chip8.change_key_state(0xA, false);
+        chip8.run_frame().unwrap();
+        assert!(!chip8.is_waiting_for_key());
+        assert_eq!(chip8.pc(), PROGRAM_START + 2);
+        assert_eq!(chip8.v()[0], 0xA);
+    }
+
+    #[test]
+    fn key_wait_on_press_finishes_on_press() {
+        let quirks = Quirks {
+            key_wait_on_press: true,
+            ..Quirks::default()
+        };
+        let mut chip8 = chip8_with_rom(quirks, &WAIT_FOR_KEY);
+        chip8.run_frame().unwrap();
+        assert!(chip8.is_waiting_for_key());
+
+        chip8.change_key_state(0xA, true);
+        chip8.run_frame().unwrap();
+        assert!(!chip8.is_waiting_for_key());
+        assert_eq!(chip8.pc(), PROGRAM_START + 2);
+        assert_eq!(chip8.v()[0], 0xA);
+    }
+
+    #[test]
+    fn key_held_before_the_wait_counts_only_once_it_changes() {
+        // Pressing it again is needed on press
+        let quirks = Quirks {
+            key_wait_on_press: true,
+            ..Quirks::default()
+        };
+        let mut chip8 = chip8_with_rom(quirks, &WAIT_FOR_KEY);
+        chip8.change_key_state(0x4, true);
+        chip8.run_frame().unwrap();
+        assert!(chip8.is_waiting_for_key());
+        chip8.change_key_state(0x4, false);
+        chip8.run_frame().unwrap();
+        assert!(chip8.is_waiting_for_key());
+        chip8.change_key_state(0x4, true);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), PROGRAM_START + 2);
+        assert_eq!(chip8.v()[0], 0x4);
+
+        // Letting go is enough on release
+        let mut chip8 = chip8_with_rom(Quirks::default(), &WAIT_FOR_KEY);
+        chip8.change_key_state(0x4, true);
+        chip8.run_frame().unwrap();
+        assert!(chip8.is_waiting_for_key());
+        chip8.change_key_state(0x4, false);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), PROGRAM_START + 2);
+        assert_eq!(chip8.v()[0], 0x4);
+    }
+
+    #[test]
+    fn loading_a_state_with_other_keys_held_keeps_waiting_for_a_key() {
+        let mut chip8 = chip8_with_rom(Quirks::default(), &WAIT_FOR_KEY);
+        chip8.run_frame().unwrap();
+        chip8.change_key_state(0x5, true);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), PROGRAM_START);
+        let state = chip8.save_state();
+
+        chip8.load_state(&state).unwrap();
+        chip8.set_keys([false; 16]);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), PROGRAM_START);
+        assert!(!chip8.keys()[0x5]);
+
+        // A key that is really pressed and released still ends the wait
+        chip8.change_key_state(0x7, true);
+        chip8.change_key_state(0x7, false);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), PROGRAM_START + 2);
+        assert_eq!(chip8.v()[0], 0x7);
+    }
+
+    #[test]
+    fn shifts_follow_the_preset() {
+        let rom = [
+            0x60, 0x05, // LD V0, 0x05
+            0x61, 0x03, // LD V1, 0x03
+            0x80, 0x16, // SHR V0, V1
+            0x62, 0x81, // LD V2, 0x81
+            0x63, 0x40, // LD V3, 0x40
+            0x82, 0x3E, // SHL V2, V3
+            0x12, 0x0C, // JP 0x20C
+        ];
+        for (quirks, v0, v2, vf) in [
+            (Quirks::CHIP8, 0x01, 0x80, 0x0),
+            (Quirks::SUPER_CHIP, 0x02, 0x02, 0x1),
+            (Quirks::XO_CHIP, 0x01, 0x80, 0x0),
+        ] {
+            let chip8 = run_rom(quirks, &rom, 1);
+            assert_eq!((chip8.v()[0], chip8.v()[2], chip8.v()[0xF]), (v0, v2, vf));
+        }
+    }
+
+    #[test]
+    fn load_and_store_move_i_per_the_preset() {
+        let rom = [
+            0xA3, 0x00, // LD I, 0x300
+            0x60, 0x11, // LD V0, 0x11
+            0x61, 0x22, // LD V1, 0x22
+            0xF1, 0x55, // LD [I], V1
+            0xF0, 0x65, // LD V0, [I]
+            0x12, 0x0A, // JP 0x20A
+        ];
+        for (quirks, i, v0) in [
+            (Quirks::CHIP8, 0x303, 0x00),
+            (Quirks::SUPER_CHIP, 0x300, 0x11),
+            (Quirks::XO_CHIP, 0x303, 0x00),
+        ] {
+            let chip8 = run_rom(quirks, &rom, 1);
+            assert_eq!(chip8.ram()[0x300..0x302], [0x11, 0x22]);
+            assert_eq!((chip8.i(), chip8.v()[0]), (i, v0));
+        }
+    }
+
+    #[test]
+    fn jump_with_offset_uses_the_preset_register() {
+        let mut rom = vec![
+            0x60, 0x04, // LD V0, 0x04
+            0x62, 0x08, // LD V2, 0x08
+            0xB2, 0x10, // JP V0, 0x210
+        ];
+        rom.resize(0x14, 0);
+        rom.extend([
+            0x6A, 0x01, // 0x214: LD VA, 0x01
+            0x12, 0x16, // JP 0x216
+            0x6A, 0x02, // 0x218: LD VA, 0x02
+            0x12, 0x1A, // JP 0x21A
+        ]);
+        for (quirks, va) in [
+            (Quirks::CHIP8, 0x01),
+            (Quirks::SUPER_CHIP, 0x02),
+            (Quirks::XO_CHIP, 0x01),
+        ] {
+            let chip8 = run_rom(quirks, &rom, 1);
+            assert_eq!(chip8.v()[0xA], va);
+        }
+    }
+
+    #[test]
+    fn logic_resets_vf_per_the_preset() {
+        let rom = [
+            0x60, 0x01, // LD V0, 0x01
+            0x61, 0x02, // LD V1, 0x02
+            0x6F, 0x05, // LD VF, 0x05
+            0x80, 0x11, // OR V0, V1
+            0x85, 0xF0, // LD V5, VF
+            0x6F, 0x05, // LD VF, 0x05
+            0x80, 0x12, // AND V0, V1
+            0x86, 0xF0, // LD V6, VF
+            0x6F, 0x05, // LD VF, 0x05
+            0x80, 0x13, // XOR V0, V1
+            0x87, 0xF0, // LD V7, VF
+            0x12, 0x18, // JP 0x218
+        ];
+        for (quirks, vf) in [
+            (Quirks::CHIP8, 0x0),
+            (Quirks::SUPER_CHIP, 0x5),
+            (Quirks::XO_CHIP, 0x5),
+        ] {
+            let chip8 = run_rom(quirks, &rom, 1);
+            assert_eq!(chip8.v()[5..=7], [vf; 3]);
+            assert_eq!(chip8.v()[0], 0x0);
+        }
+    }
+
+    #[test]
+    fn sprites_clip_or_wrap_per_the_preset() {
+        let rom = [
+            0x60, 0x3E, // LD V0, 62
+            0x61, 0x1F, // LD V1, 31
+            0xA2, 0x0A, // LD I, 0x20A
+            0xD0, 0x12, // DRW V0, V1, 2
+            0x12, 0x08, // JP 0x208
+            0xFF, 0xFF, // sprite
+        ];
+        let clipped = vec![(62, 31), (63, 31)];
+        let mut wrapped: Vec<(usize, usize)> = (0..6)
+            .chain(62..64)
+            .flat_map(|x| [(x, 0), (x, 31)])
+            .collect();
+        wrapped.sort();
+        for (quirks, expected) in [
+            (Quirks::CHIP8, &clipped),
+            (Quirks::SUPER_CHIP, &clipped),
+            (Quirks::XO_CHIP, &wrapped),
+        ] {
+            let chip8 = run_rom(quirks, &rom, 2);
+            assert_eq!(&lit_pixels(&chip8), expected);
+        }
+    }
+
+    #[test]
+    fn display_wait_draws_once_per_frame() {
+        let rom = [
+            0xD0, 0x01, // DRW V0, V0, 1
+            0x72, 0x01, // ADD V2, 1
+            0x12, 0x00, // JP 0x200
+        ];
+        for (quirks, draws) in [
+            (Quirks::CHIP8, 10),
+            (Quirks::SUPER_CHIP, 100),
+            (Quirks::XO_CHIP, 100),
+        ] {
+            let mut chip8 = chip8_with_rom(quirks, &rom);
+            chip8.set_timing_model(TimingModel::InstructionsPerFrame(30));
+            for _ in 0..10 {
+                chip8.run_frame().unwrap();
             }
+            assert_eq!(chip8.v()[2], draws);
+        }
+    }
 
-            (_, _, _, _) => panic!("Unexpected opcode"),
+    #[test]
+    fn high_and_low_switch_resolution_and_clear_the_display() {
+        let rom = [
+            0x00, 0xFF, // HIGH
+            0x60, 0x7F, // LD V0, 127
+            0x61, 0x3F, // LD V1, 63
+            0xA2, 0x10, // LD I, 0x210
+            0xD0, 0x11, // DRW V0, V1, 1
+            0x12, 0x0A, // JP 0x20A
+            0x00, 0xFE, // 0x20C: LOW
+            0x12, 0x0E, // JP 0x20E
+            0x80, // sprite
+        ];
+        let mut chip8 = run_rom(Quirks::SUPER_CHIP, &rom, 2);
+        assert!(chip8.is_hires());
+        assert_eq!(chip8.vram.len(), HIRES_DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT);
+        assert_eq!(lit_pixels(&chip8), [(127, 63)]);
+
+        chip8.set_pc(0x20C);
+        chip8.run_frame().unwrap();
+        assert!(!chip8.is_hires());
+        assert_eq!(chip8.vram.len(), DISPLAY_WIDTH * DISPLAY_HEIGHT);
+        assert_eq!(lit_pixels(&chip8), []);
+    }
+
+    #[test]
+    fn scrolling_moves_the_display() {
+        let rom = [
+            0x00, 0xFF, // HIGH
+            0x60, 0x0A, // LD V0, 10
+            0xA2, 0x14, // LD I, 0x214
+            0xD0, 0x01, // DRW V0, V0, 1
+            0x00, 0xC3, // SCD 3
+            0x00, 0xFB, // SCR
+            0x00, 0xFB, // SCR
+            0x00, 0xFC, // SCL
+            0x12, 0x10, // JP 0x210
+            0x00, 0x00, // padding
+            0x80, // sprite
+        ];
+        let chip8 = run_rom(Quirks::SUPER_CHIP, &rom, 3);
+        assert_eq!(lit_pixels(&chip8), [(14, 13)]);
+    }
+
+    #[test]
+    fn dxy0_draws_a_16x16_sprite() {
+        let mut rom = vec![
+            0x00, 0xFF, // HIGH
+            0x60, 0x10, // LD V0, 16
+            0x61, 0x08, // LD V1, 8
+            0xA2, 0x0C, // LD I, 0x20C
+            0xD0, 0x10, // DRW V0, V1, 0
+            0x12, 0x0A, // JP 0x20A
+        ];
+        // Two bytes per row, the leftmost and rightmost pixels set
+        rom.extend([0x80, 0x01].repeat(16));
+        let chip8 = run_rom(Quirks::SUPER_CHIP, &rom, 2);
+        let mut expected: Vec<(usize, usize)> = (8..24).flat_map(|y| [(16, y), (31, y)]).collect();
+        expected.sort();
+        assert_eq!(lit_pixels(&chip8), expected);
+        assert_eq!(chip8.v()[0xF], 0x0);
+    }
+
+    #[test]
+    fn big_font_digits() {
+        let rom = [
+            0x60, 0x09, // LD V0, 9
+            0xF0, 0x30, // LD HF, V0
+            0x12, 0x04, // JP 0x204
+        ];
+        let chip8 = run_rom(Quirks::SUPER_CHIP, &rom, 1);
+        let i = chip8.i() as usize;
+        assert_eq!(i, BIG_FONT_ADDRESS + 90);
+        assert_eq!(chip8.ram()[i..i + 10], BIG_FONT[90..100]);
+    }
+
+    #[test]
+    fn flag_registers_keep_what_was_saved() {
+        let rom = [
+            0x60, 0x11, // LD V0, 0x11
+            0x61, 0x22, // LD V1, 0x22
+            0x62, 0x33, // LD V2, 0x33
+            0xF2, 0x75, // LD R, V2
+            0x60, 0x00, // LD V0, 0
+            0x61, 0x00, // LD V1, 0
+            0x62, 0x00, // LD V2, 0
+            0xF1, 0x85, // LD V1, R
+            0x12, 0x10, // JP 0x210
+        ];
+        let chip8 = run_rom(Quirks::SUPER_CHIP, &rom, 1);
+        assert_eq!(chip8.v()[..3], [0x11, 0x22, 0x00]);
+    }
+
+    #[test]
+    fn save_states_restore_the_resolution_they_were_made_in() {
+        let rom = [
+            0xA2, 0x10, // LD I, 0x210
+            0x60, 0x05, // LD V0, 5
+            0xD0, 0x01, // DRW V0, V0, 1
+            0x12, 0x06, // JP 0x206
+            0x00, 0xFF, // 0x208: HIGH
+            0x60, 0x64, // LD V0, 100
+            0xD0, 0x01, // DRW V0, V0, 1
+            0x12, 0x0E, // JP 0x20E
+            0x80, // sprite
+        ];
+        let mut chip8 = run_rom(Quirks::SUPER_CHIP, &rom, 2);
+        let lores_state = chip8.save_state();
+        chip8.set_pc(0x208);
+        for _ in 0..2 {
+            chip8.run_frame().unwrap();
         }
+        let hires_state = chip8.save_state();
+
+        chip8.load_state(&lores_state).unwrap();
+        assert!(!chip8.is_hires());
+        assert_eq!(chip8.vram.len(), DISPLAY_WIDTH * DISPLAY_HEIGHT);
+        assert_eq!(lit_pixels(&chip8), [(5, 5)]);
+
+        chip8.load_state(&hires_state).unwrap();
+        assert!(chip8.is_hires());
+        assert_eq!(chip8.vram.len(), HIRES_DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT);
+        assert_eq!(lit_pixels(&chip8), [(100, 36)]);
+        assert_eq!(chip8.save_state(), hires_state);
+    }
+
+    #[test]
+    fn long_loads_set_all_of_i_and_are_skipped_whole() {
+        let rom = [
+            0x60, 0x00, // LD V0, 0
+            0x30, 0x00, // SE V0, 0
+            0xF0, 0x00, 0xAB, 0xCD, // LD I, LONG 0xABCD
+            0xF0, 0x00, 0x12, 0x34, // LD I, LONG 0x1234
+            0x12, 0x0C, // JP 0x20C
+        ];
+        let chip8 = run_rom(Quirks::XO_CHIP, &rom, 1);
+        assert_eq!(chip8.i(), 0x1234);
+        assert_eq!(chip8.pc(), 0x20C);
+    }
+
+    #[test]
+    fn register_ranges_save_and_load_in_either_order() {
+        let rom = [
+            0xA3, 0x00, // LD I, 0x300
+            0x61, 0x22, // LD V1, 0x22
+            0x62, 0x33, // LD V2, 0x33
+            0x63, 0x44, // LD V3, 0x44
+            0x53, 0x12, // SAVE V3, V1
+            0x54, 0x63, // LOAD V4, V6
+            0x12, 0x0C, // JP 0x20C
+        ];
+        let chip8 = run_rom(Quirks::XO_CHIP, &rom, 1);
+        assert_eq!(chip8.ram()[0x300..0x303], [0x44, 0x33, 0x22]);
+        assert_eq!(chip8.v()[4..7], [0x44, 0x33, 0x22]);
+        assert_eq!(chip8.i(), 0x300);
+    }
+
+    #[test]
+    fn drawing_and_clearing_only_touch_the_selected_planes() {
+        let rom = [
+            0xF2, 0x01, // PLANE 2
+            0xA2, 0x14, // LD I, 0x214
+            0x60, 0x00, // LD V0, 0
+            0xD0, 0x01, // DRW V0, V0, 1
+            0xF3, 0x01, // PLANE 3
+            0x60, 0x08, // LD V0, 8
+            0xD0, 0x01, // DRW V0, V0, 1
+            0xF1, 0x01, // PLANE 1
+            0x00, 0xE0, // CLS
+            0x12, 0x12, // JP 0x212
+            0x80, 0xC0, // one sprite for each plane
+        ];
+        let chip8 = run_rom(Quirks::XO_CHIP, &rom, 3);
+        let pixel = |chip8: &Chip8, x: usize, y: usize| chip8.vram[y * DISPLAY_WIDTH + x];
+        assert_eq!(pixel(&chip8, 0, 0), 0x2);
+        // The first plane was cleared from the pixel both planes drew
+        assert_eq!(pixel(&chip8, 8, 8), 0x2);
+        assert_eq!(pixel(&chip8, 9, 8), 0x2);
+        assert_eq!(lit_pixels(&chip8), [(0, 0), (8, 8), (9, 8)]);
+        assert_eq!(chip8.planes(), 0x1);
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        let mut rom = vec![
+            0xA2, 0x0A, // LD I, 0x20A
+            0xF0, 0x02, // AUDIO
+            0x60, 0x70, // LD V0, 112
+            0xF0, 0x3A, // PITCH V0
+            0x12, 0x08, // JP 0x208
+        ];
+        let pattern: Vec<u8> = (0..16).collect();
+        rom.extend(&pattern);
+        let mut chip8 = chip8_with_rom(Quirks::XO_CHIP, &rom);
+        assert_eq!(chip8.audio_pattern(), None);
+        assert_eq!(chip8.audio_pattern_rate(), 4000.0);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.audio_pattern().unwrap()[..], pattern[..]);
+        // 48 steps up doubles the rate
+        assert_eq!(chip8.audio_pattern_rate(), 8000.0);
+    }
+
+    #[test]
+    fn running_off_the_end_of_memory_faults() {
+        let mut chip8 = Chip8::new(Quirks::XO_CHIP, 0);
+        chip8.ram_mut()[0xFFFE..].copy_from_slice(&[0x60, 0x01]); // LD V0, 1
+        chip8.set_pc(0xFFFE);
+        assert_eq!(
+            chip8.run_frame().err(),
+            Some(Chip8Error::MemoryOutOfBounds {
+                address: 0xFFFE,
+                location: MEMORY_SIZE,
+            })
+        );
+
+        // Skipping past the end too
+        let mut chip8 = Chip8::new(Quirks::XO_CHIP, 0);
+        chip8.ram_mut()[0xFFFC..].copy_from_slice(&[0x30, 0x00, 0x00, 0x00]); // SE V0, 0
+        chip8.set_pc(0xFFFC);
+        assert_eq!(
+            chip8.run_frame().err(),
+            Some(Chip8Error::MemoryOutOfBounds {
+                address: 0xFFFC,
+                location: MEMORY_SIZE,
+            })
+        );
     }
 }
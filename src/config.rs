@@ -1,17 +1,308 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+
+use serde::Deserialize;
+
+use crate::keymap::{Keymap, KeymapFile};
+use crate::movie::Movie;
+use crate::quirks::Quirks;
+use crate::rom_database::RomDatabase;
+use crate::timing::{TimingModel, DEFAULT_INSTRUCTIONS_PER_FRAME, DEFAULT_INSTRUCTIONS_PER_SECOND};
 
 /// A toy chip-8 interpreter
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Config {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// Rom file path
+    #[clap(value_parser, required = true)]
+    pub rom_path: Option<String>,
+
+    /// Background color of display, defaults to 0x000000
+    #[clap(long, value_parser)]
+    pub background_color: Option<u32>,
+    /// Sprite color, defaults to 0xFFFFFF
+    #[clap(long, value_parser)]
+    pub sprite_color: Option<u32>,
+    /// Color of pixels set only in the second XO-CHIP plane, defaults to 0xAAAAAA
+    #[clap(long, value_parser)]
+    pub plane2_color: Option<u32>,
+    /// Color of pixels set in both XO-CHIP planes, defaults to 0x555555
+    #[clap(long, value_parser)]
+    pub overlap_color: Option<u32>,
+    /// Wait for the monitor's vertical blank when presenting a frame
+    #[clap(long, action)]
+    pub vsync: bool,
+    /// Initial window size as a multiple of the 64x32 display
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..64), default_value_t = 12)]
+    pub scale: u32,
+    /// Only scale the display by whole numbers, the rest of the window is left black
+    #[clap(long, action)]
+    pub integer_scale: bool,
+    /// Filter that softens flickering sprites
+    #[clap(long, value_enum, default_value_t = DisplayFilter::None)]
+    pub display_filter: DisplayFilter,
+    /// Share of brightness a cleared pixel keeps each frame with the phosphor filter
+    #[clap(long, value_parser, default_value_t = 0.6)]
+    pub phosphor_decay: f32,
+
+    /// Frequency of the sound timer tone in Hz
+    #[clap(long, value_parser, default_value_t = 440.0)]
+    pub audio_frequency: f32,
+    /// Volume of the sound timer tone, between 0.0 and 1.0
+    #[clap(long, value_parser, default_value_t = 0.25)]
+    pub volume: f32,
+    /// Waveform of the sound timer tone
+    #[clap(long, value_enum, default_value_t = Waveform::Square)]
+    pub waveform: Waveform,
+    /// Disable sound
+    #[clap(long, action)]
+    pub mute: bool,
+
+    /// Interpreter whose quirks the rom expects, defaults to chip8
+    #[clap(long, value_enum)]
+    pub quirks: Option<QuirksPreset>,
+    /// Finish FX0A when a key is pressed instead of when it's released
+    #[clap(long, action)]
+    pub key_wait_on_press: bool,
+    /// Seed for the RND instruction, random if not given
+    #[clap(long, value_parser)]
+    pub seed: Option<u64>,
+    /// How long instructions take to run, defaults to vip
+    #[clap(long, value_enum)]
+    pub timing: Option<Timing>,
+    /// Instructions per frame or per second for the ipf and ips timings
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub speed: Option<u32>,
+
+    /// Start paused with the terminal debugger attached
+    #[clap(long, action, conflicts_with_all = &["record", "replay"])]
+    pub debug: bool,
+    /// Reload and reset whenever the rom file changes
+    #[clap(long, action)]
+    pub watch: bool,
+    /// Keep debugger breakpoints and watchpoints when --watch reloads the rom
+    #[clap(long, action, requires = "watch")]
+    pub keep_breakpoints: bool,
+
+    /// Keymap file mapping SDL key names to the hex keypad
+    #[clap(long, value_parser)]
+    pub keymap: Option<String>,
+    /// Print the keymap used for the rom and exit
+    #[clap(long, action)]
+    pub print_keymap: bool,
+    /// Per-rom settings file keyed by SHA-1, command line flags take precedence
+    #[clap(long, value_parser)]
+    pub rom_database: Option<String>,
+
+    /// Seconds of gameplay the rewind hotkey can go back, 0 turns rewinding off
+    #[clap(long, value_parser, default_value_t = 120)]
+    pub rewind_seconds: u32,
+    /// Frames between rewind snapshots
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = 1)]
+    pub rewind_interval: u32,
+    /// Memory the rewind history may use in MiB
+    #[clap(long, value_parser, default_value_t = 16)]
+    pub rewind_memory: usize,
+
+    /// Record keypad input to a movie file, written when the emulator quits
+    #[clap(long, value_parser, conflicts_with = "replay")]
+    pub record: Option<String>,
+    /// Play back the input of a movie file and check it stays in sync
+    #[clap(long, value_parser)]
+    pub replay: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print the disassembly of a rom
+    Disasm {
+        /// Rom file path
+        #[clap(value_parser)]
+        rom_path: String,
+    },
+    /// Assemble a source file into a rom
+    Asm {
+        /// Source file path
+        #[clap(value_parser)]
+        source_path: String,
+        /// Output rom path, defaults to the source path with a .ch8 extension
+        #[clap(short, long, value_parser)]
+        output: Option<String>,
+        /// Run the assembled rom in the emulator
+        #[clap(long, action)]
+        run: bool,
+    },
+}
+
+impl Config {
+    /// Default settings for running `rom_path`.
+    pub fn for_rom(rom_path: &str) -> Self {
+        Config::parse_from([env!("CARGO_PKG_NAME"), rom_path])
+    }
+}
+
+/// Command line flags together with the files they point to.
+pub struct Settings {
+    pub config: Config,
+    pub keymap_file: Option<KeymapFile>,
+    pub rom_database: RomDatabase,
+    pub replay: Option<Movie>,
+}
+
+/// What a single rom runs with: command line flags first, then its rom
+/// database entry, then the defaults.
+pub struct RomConfig {
+    pub name: Option<String>,
+    pub quirks: Quirks,
+    pub timing_model: TimingModel,
+    /// Background, first plane, second plane and overlapping planes colors.
+    pub palette: [u32; 4],
+    pub keymap: Keymap,
+}
+
+impl Settings {
+    pub fn for_rom(&self, rom_path: &str, rom_data: &[u8]) -> RomConfig {
+        let config = &self.config;
+        let entry = self
+            .rom_database
+            .lookup(rom_data)
+            .cloned()
+            .unwrap_or_default();
+
+        let keymap = match &self.keymap_file {
+            Some(keymap_file) => keymap_file.keymap(rom_path),
+            None => entry.keymap(),
+        };
+        // A database speed only makes sense with the timing it was chosen for
+        let (timing, speed) = match config.timing {
+            Some(timing) => (timing, config.speed),
+            None => (
+                entry.timing.unwrap_or(Timing::Vip),
+                config.speed.or(entry.speed),
+            ),
+        };
+        let color = |flag: Option<u32>, entry_color: Option<u32>, default| {
+            flag.or(entry_color).unwrap_or(default)
+        };
+        let mut quirks = config
+            .quirks
+            .or(entry.quirks)
+            .unwrap_or(QuirksPreset::Chip8)
+            .quirks();
+        quirks.key_wait_on_press |= config.key_wait_on_press;
+        RomConfig {
+            quirks,
+            timing_model: timing.timing_model(speed),
+            palette: [
+                color(config.background_color, entry.background_color, 0x000000),
+                color(config.sprite_color, entry.sprite_color, 0xFFFFFF),
+                color(config.plane2_color, entry.plane2_color, 0xAAAAAA),
+                color(config.overlap_color, entry.overlap_color, 0x555555),
+            ],
+            keymap,
+            name: entry.name,
+        }
+    }
+}
+
+/// Runs a chip-8 rom without a display and dumps the screen
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct HeadlessConfig {
     /// Rom file path
     #[clap(value_parser)]
     pub rom_path: String,
 
-    /// Background color of display
-    #[clap(long, value_parser, default_value_t = 0x000000)]
-    pub background_color: u32,
-    /// Sprite color
-    #[clap(long, value_parser, default_value_t = 0xFFFFFF)]
-    pub sprite_color: u32,
+    /// Number of 60Hz frames to run
+    #[clap(long, value_parser, conflicts_with = "cycles")]
+    pub frames: Option<u32>,
+    /// Number of instructions to run, stops early if the rom waits for a key
+    #[clap(long, value_parser)]
+    pub cycles: Option<u64>,
+
+    /// Write the final screen as ASCII art to this file instead of stdout
+    #[clap(long, value_parser)]
+    pub output: Option<String>,
+    /// Compare the final screen against this ASCII art file
+    #[clap(long, value_parser)]
+    pub reference: Option<String>,
+
+    /// Interpreter whose quirks the rom expects
+    #[clap(long, value_enum, default_value_t = QuirksPreset::Chip8)]
+    pub quirks: QuirksPreset,
+    /// Seed for the RND instruction
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub seed: u64,
+    /// How long instructions take to run
+    #[clap(long, value_enum, default_value_t = Timing::Vip)]
+    pub timing: Timing,
+    /// Instructions per frame or per second for the ipf and ips timings
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub speed: Option<u32>,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuirksPreset {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl QuirksPreset {
+    pub fn quirks(self) -> Quirks {
+        match self {
+            QuirksPreset::Chip8 => Quirks::CHIP8,
+            QuirksPreset::SuperChip => Quirks::SUPER_CHIP,
+            QuirksPreset::XoChip => Quirks::XO_CHIP,
+        }
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Timing {
+    /// Per-opcode COSMAC VIP costs
+    Vip,
+    /// Fixed instructions per frame
+    Ipf,
+    /// Fixed instructions per second
+    Ips,
+}
+
+impl Timing {
+    pub fn timing_model(self, speed: Option<u32>) -> TimingModel {
+        match self {
+            Timing::Vip => TimingModel::Vip,
+            Timing::Ipf => {
+                TimingModel::InstructionsPerFrame(speed.unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME))
+            }
+            Timing::Ips => {
+                TimingModel::InstructionsPerSecond(speed.unwrap_or(DEFAULT_INSTRUCTIONS_PER_SECOND))
+            }
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayFilter {
+    /// Show every frame as it is
+    None,
+    /// Cleared pixels fade out by --phosphor-decay each frame
+    Phosphor,
+    /// Mix each frame with the one before it
+    Blend,
+    /// Cleared pixels stay lit for one more frame
+    Ghosting,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
 }
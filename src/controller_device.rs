@@ -0,0 +1,327 @@
+use std::collections::{HashMap, HashSet};
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+
+use crate::keymap::Keymap;
+
+// How far a stick or trigger has to move before it counts as pressed
+const AXIS_THRESHOLD: i16 = 16000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ControllerInput {
+    Button(Button),
+    AxisNegative(Axis),
+    AxisPositive(Axis),
+}
+
+impl ControllerInput {
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if let Some(axis) = name.strip_suffix('-') {
+            Axis::from_string(axis).map(ControllerInput::AxisNegative)
+        } else if let Some(axis) = name.strip_suffix('+') {
+            Axis::from_string(axis).map(ControllerInput::AxisPositive)
+        } else {
+            Button::from_string(&name).map(ControllerInput::Button)
+        }
+    }
+}
+
+/// Game controllers mapped to the hex keypad, opened and closed as they are
+/// plugged in and out.
+pub struct ControllerDevice {
+    // None if SDL couldn't start its game controller support
+    subsystem: Option<GameControllerSubsystem>,
+    controllers: HashMap<u32, GameController>,
+    inputs: ControllerInputs,
+}
+
+impl ControllerDevice {
+    pub fn new(sdl_context: &sdl2::Sdl, keymap: &Keymap) -> Self {
+        let subsystem = sdl_context
+            .game_controller()
+            .map_err(|error| {
+                eprintln!("Couldn't start game controller support, controllers are off: {error}")
+            })
+            .ok();
+        let mut controller_device = ControllerDevice {
+            subsystem,
+            controllers: HashMap::new(),
+            inputs: ControllerInputs::default(),
+        };
+        controller_device.set_keymap(keymap);
+        controller_device
+    }
+
+    /// Replaces the controller mapping, held inputs stay held.
+    pub fn set_keymap(&mut self, keymap: &Keymap) {
+        self.inputs.set_keymap(keymap);
+    }
+
+    /// Handles controller events, returns the keypad keys to press or release.
+    /// Controllers connected at startup also arrive as `ControllerDeviceAdded`.
+    pub fn handle_event(&mut self, event: &Event) -> Vec<(u8, bool)> {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                let Some(subsystem) = &self.subsystem else {
+                    return Vec::new();
+                };
+                match subsystem.open(which) {
+                    Ok(controller) => {
+                        println!("Connected controller {}", controller.name());
+                        self.controllers
+                            .insert(controller.instance_id(), controller);
+                    }
+                    Err(error) => eprintln!("Couldn't open controller {which}: {error}"),
+                }
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(controller) = self.controllers.remove(&which) {
+                    println!("Disconnected controller {}", controller.name());
+                }
+            }
+            _ => (),
+        }
+        self.inputs.handle_event(event)
+    }
+}
+
+/// Which inputs are held on each controller and the keypad keys they map to.
+#[derive(Default)]
+struct ControllerInputs {
+    mapping: HashMap<ControllerInput, u8>,
+    // Inputs held down on each controller, by joystick id
+    pressed: HashSet<(u32, ControllerInput)>,
+}
+
+impl ControllerInputs {
+    fn set_keymap(&mut self, keymap: &Keymap) {
+        self.mapping.clear();
+        for (name, chip8_key) in keymap.buttons() {
+            match ControllerInput::from_name(name) {
+                Some(input) => {
+                    self.mapping.insert(input, chip8_key);
+                }
+                None => eprintln!("Ignoring unknown controller input {name:?} in keymap"),
+            }
+        }
+    }
+
+    /// Keypad keys to press or release for an event, everything held on a
+    /// removed controller is released.
+    fn handle_event(&mut self, event: &Event) -> Vec<(u8, bool)> {
+        match *event {
+            Event::ControllerDeviceRemoved { which, .. } => {
+                let released: Vec<ControllerInput> = self
+                    .pressed
+                    .iter()
+                    .filter(|(id, _)| *id == which)
+                    .map(|(_, input)| *input)
+                    .collect();
+                released
+                    .into_iter()
+                    .filter_map(|input| self.set_pressed(which, input, false))
+                    .collect()
+            }
+            Event::ControllerButtonDown { which, button, .. } => self
+                .set_pressed(which, ControllerInput::Button(button), true)
+                .into_iter()
+                .collect(),
+            Event::ControllerButtonUp { which, button, .. } => self
+                .set_pressed(which, ControllerInput::Button(button), false)
+                .into_iter()
+                .collect(),
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => [
+                self.set_pressed(
+                    which,
+                    ControllerInput::AxisNegative(axis),
+                    value < -AXIS_THRESHOLD,
+                ),
+                self.set_pressed(
+                    which,
+                    ControllerInput::AxisPositive(axis),
+                    value > AXIS_THRESHOLD,
+                ),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn set_pressed(
+        &mut self,
+        which: u32,
+        input: ControllerInput,
+        pressed: bool,
+    ) -> Option<(u8, bool)> {
+        let changed = if pressed {
+            self.pressed.insert((which, input))
+        } else {
+            self.pressed.remove(&(which, input))
+        };
+        let chip8_key = *self.mapping.get(&input)?;
+        // Inputs mapped to the same key hold it down together
+        let held_by_others = self.pressed.iter().any(|(id, held)| {
+            (*id, *held) != (which, input) && self.mapping.get(held) == Some(&chip8_key)
+        });
+        (changed && !held_by_others).then_some((chip8_key, pressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs() -> ControllerInputs {
+        let mut inputs = ControllerInputs::default();
+        inputs.set_keymap(&Keymap::default());
+        inputs
+    }
+
+    fn button(which: u32, button: Button, pressed: bool) -> Event {
+        if pressed {
+            Event::ControllerButtonDown {
+                timestamp: 0,
+                which,
+                button,
+            }
+        } else {
+            Event::ControllerButtonUp {
+                timestamp: 0,
+                which,
+                button,
+            }
+        }
+    }
+
+    fn axis(which: u32, axis: Axis, value: i16) -> Event {
+        Event::ControllerAxisMotion {
+            timestamp: 0,
+            which,
+            axis,
+            value,
+        }
+    }
+
+    #[test]
+    fn input_names() {
+        assert_eq!(
+            ControllerInput::from_name("DPUp"),
+            Some(ControllerInput::Button(Button::DPadUp))
+        );
+        assert_eq!(
+            ControllerInput::from_name("leftx-"),
+            Some(ControllerInput::AxisNegative(Axis::LeftX))
+        );
+        assert_eq!(
+            ControllerInput::from_name("righttrigger+"),
+            Some(ControllerInput::AxisPositive(Axis::TriggerRight))
+        );
+        assert_eq!(ControllerInput::from_name("nope"), None);
+        assert_eq!(ControllerInput::from_name("nope+"), None);
+    }
+
+    #[test]
+    fn buttons_press_and_release_keys() {
+        let mut inputs = inputs();
+        assert_eq!(
+            inputs.handle_event(&button(0, Button::A, true)),
+            [(0x5, true)]
+        );
+        // Repeated presses don't press the key again
+        assert_eq!(inputs.handle_event(&button(0, Button::A, true)), []);
+        assert_eq!(
+            inputs.handle_event(&button(0, Button::A, false)),
+            [(0x5, false)]
+        );
+        assert_eq!(inputs.handle_event(&button(0, Button::A, false)), []);
+    }
+
+    #[test]
+    fn unmapped_inputs_are_ignored() {
+        let mut inputs = inputs();
+        assert_eq!(inputs.handle_event(&button(0, Button::Start, true)), []);
+        assert_eq!(inputs.handle_event(&axis(0, Axis::RightX, i16::MAX)), []);
+        assert_eq!(inputs.handle_event(&button(0, Button::Start, false)), []);
+        assert_eq!(inputs.handle_event(&axis(0, Axis::RightX, 0)), []);
+    }
+
+    #[test]
+    fn axes_press_past_the_threshold() {
+        let mut inputs = inputs();
+        assert_eq!(
+            inputs.handle_event(&axis(0, Axis::LeftX, AXIS_THRESHOLD)),
+            []
+        );
+        assert_eq!(
+            inputs.handle_event(&axis(0, Axis::LeftX, AXIS_THRESHOLD + 1)),
+            [(0x6, true)]
+        );
+        assert_eq!(inputs.handle_event(&axis(0, Axis::LeftX, i16::MAX)), []);
+        // Flicking straight to the other side releases one key and presses the other
+        assert_eq!(
+            inputs.handle_event(&axis(0, Axis::LeftX, -AXIS_THRESHOLD - 1)),
+            [(0x4, true), (0x6, false)]
+        );
+        assert_eq!(
+            inputs.handle_event(&axis(0, Axis::LeftX, -AXIS_THRESHOLD)),
+            [(0x4, false)]
+        );
+    }
+
+    #[test]
+    fn keys_stay_down_while_any_input_mapped_to_them_is_held() {
+        let mut inputs = inputs();
+        assert_eq!(
+            inputs.handle_event(&button(0, Button::DPadUp, true)),
+            [(0x2, true)]
+        );
+        assert_eq!(inputs.handle_event(&axis(0, Axis::LeftY, i16::MIN)), []);
+        assert_eq!(inputs.handle_event(&button(0, Button::DPadUp, false)), []);
+        assert_eq!(
+            inputs.handle_event(&axis(0, Axis::LeftY, 0)),
+            [(0x2, false)]
+        );
+
+        // The same goes for two controllers
+        assert_eq!(
+            inputs.handle_event(&button(1, Button::A, true)),
+            [(0x5, true)]
+        );
+        assert_eq!(inputs.handle_event(&button(2, Button::A, true)), []);
+        assert_eq!(inputs.handle_event(&button(1, Button::A, false)), []);
+        assert_eq!(
+            inputs.handle_event(&Event::ControllerDeviceRemoved {
+                timestamp: 0,
+                which: 2,
+            }),
+            [(0x5, false)]
+        );
+    }
+
+    #[test]
+    fn removing_a_controller_releases_what_it_held() {
+        let mut inputs = inputs();
+        inputs.handle_event(&button(1, Button::DPadUp, true));
+        inputs.handle_event(&axis(1, Axis::LeftY, i16::MAX));
+        inputs.handle_event(&button(2, Button::B, true));
+
+        let mut released = inputs.handle_event(&Event::ControllerDeviceRemoved {
+            timestamp: 0,
+            which: 1,
+        });
+        released.sort();
+        assert_eq!(released, [(0x2, false), (0x8, false)]);
+        // The other controller keeps its button held
+        assert_eq!(
+            inputs.handle_event(&button(2, Button::B, false)),
+            [(0x0, false)]
+        );
+    }
+}
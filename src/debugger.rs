@@ -0,0 +1,417 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::chip8::{Chip8, Chip8Hooks, MEMORY_SIZE};
+use crate::disassembler::mnemonic_at;
+
+const HELP: &str = "\
+Debugger commands:
+  c, continue          resume execution
+  p, pause             pause execution
+  s, step              run one instruction
+  n, next              run one instruction, stepping over CALL
+  f, finish            run until the current subroutine returns
+  b [addr]             add a breakpoint at addr, or list breakpoints
+  d <addr>             delete the breakpoint at addr
+  w [target]           watch a register (v0-vf, i) or memory address, or list watchpoints
+  uw <target>          remove a watchpoint
+  r, regs              show registers, stack and timers
+  l, list [addr]       disassemble around addr or PC
+  x <addr> [len]       dump memory
+  h, help              show this help
+Addresses are hexadecimal.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    Memory(u16),
+    Register(u8),
+    I,
+}
+
+impl Watchpoint {
+    fn parse(target: &str) -> Option<Self> {
+        let target = target.to_ascii_lowercase();
+        if target == "i" {
+            Some(Watchpoint::I)
+        } else if let Some(register) = target.strip_prefix('v') {
+            u8::from_str_radix(register, 16)
+                .ok()
+                .filter(|register| *register <= 0xF)
+                .map(Watchpoint::Register)
+        } else {
+            parse_address(&target).map(Watchpoint::Memory)
+        }
+    }
+
+    fn value(&self, chip8: &Chip8) -> u16 {
+        match self {
+            Watchpoint::Memory(address) => chip8.ram()[*address as usize] as u16,
+            Watchpoint::Register(register) => chip8.v()[*register as usize] as u16,
+            Watchpoint::I => chip8.i(),
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchpoint::Memory(address) => write!(f, "[{address:#05X}]"),
+            Watchpoint::Register(register) => write!(f, "V{register:X}"),
+            Watchpoint::I => write!(f, "I"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+    Running,
+    Paused,
+    Step,
+    StepOver { address: u16, sp: u8 },
+    RunToReturn { sp: u8 },
+}
+
+/// Terminal debugger driven through `Chip8Hooks`.
+pub struct Debugger {
+    mode: RunMode,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    watched_values: Vec<u16>,
+    // Lets execution leave a breakpoint it is currently stopped on
+    resume_address: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            mode: RunMode::Paused,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            watched_values: Vec::new(),
+            resume_address: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == RunMode::Paused
+    }
+
+    pub fn pause(&mut self, chip8: &Chip8) {
+        self.mode = RunMode::Paused;
+        Self::print_location(chip8);
+    }
+
+    /// Forgets stepping state after the machine was reset, breakpoints and
+    /// watchpoints go too unless `keep_breakpoints` is set.
+    pub fn reset(&mut self, keep_breakpoints: bool) {
+        if !matches!(self.mode, RunMode::Running | RunMode::Paused) {
+            self.mode = RunMode::Paused;
+        }
+        self.resume_address = None;
+        if !keep_breakpoints {
+            self.breakpoints.clear();
+            self.watchpoints.clear();
+        }
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    pub fn print_help() {
+        println!("{HELP}");
+    }
+
+    pub fn execute_command(&mut self, line: &str, chip8: &Chip8) {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return;
+        };
+        let argument = words.next();
+
+        match (command, argument) {
+            ("c" | "continue", _) => self.resume(RunMode::Running, chip8),
+            ("p" | "pause", _) => self.pause(chip8),
+            ("s" | "step", _) => self.resume(RunMode::Step, chip8),
+            ("n" | "next", _) => {
+                let pc = chip8.pc() as usize;
+                let is_call = pc < MEMORY_SIZE && chip8.ram()[pc] & 0xF0 == 0x20;
+                // A CALL in the last word faults before it could ever return
+                match chip8.pc().checked_add(2).filter(|_| is_call) {
+                    Some(address) => {
+                        let mode = RunMode::StepOver {
+                            address,
+                            sp: chip8.sp(),
+                        };
+                        self.resume(mode, chip8);
+                    }
+                    None => self.resume(RunMode::Step, chip8),
+                }
+            }
+            ("f" | "finish", _) => {
+                if chip8.sp() == 0 {
+                    println!("Not inside a subroutine");
+                } else {
+                    self.resume(RunMode::RunToReturn { sp: chip8.sp() }, chip8);
+                }
+            }
+            ("b", None) => {
+                for address in &self.breakpoints {
+                    println!("Breakpoint at {address:#05X}");
+                }
+            }
+            ("b", Some(address)) => match parse_address(address) {
+                Some(address) => {
+                    self.breakpoints.insert(address);
+                    println!("Breakpoint at {address:#05X}");
+                }
+                None => println!("Invalid address {address}"),
+            },
+            ("d", Some(address)) => match parse_address(address) {
+                Some(address) if self.breakpoints.remove(&address) => {
+                    println!("Deleted breakpoint at {address:#05X}");
+                }
+                _ => println!("No breakpoint at {address}"),
+            },
+            ("w", None) => {
+                for watchpoint in &self.watchpoints {
+                    println!("Watching {watchpoint}");
+                }
+            }
+            ("w", Some(target)) => match Watchpoint::parse(target) {
+                Some(watchpoint) => {
+                    if !self.watchpoints.contains(&watchpoint) {
+                        self.watchpoints.push(watchpoint);
+                    }
+                    println!("Watching {watchpoint}");
+                }
+                None => println!("Invalid watch target {target}"),
+            },
+            ("uw", Some(target)) => match Watchpoint::parse(target) {
+                Some(watchpoint) if self.watchpoints.contains(&watchpoint) => {
+                    self.watchpoints.retain(|watched| *watched != watchpoint);
+                    println!("Stopped watching {watchpoint}");
+                }
+                _ => println!("Not watching {target}"),
+            },
+            ("r" | "regs", _) => Self::print_registers(chip8),
+            ("l" | "list", address) => {
+                let address = address.and_then(parse_address).unwrap_or(chip8.pc());
+                self.print_disassembly(chip8, address);
+            }
+            ("x", Some(address)) => match parse_address(address) {
+                Some(address) => {
+                    let len = words
+                        .next()
+                        .and_then(|len| usize::from_str_radix(len, 16).ok())
+                        .unwrap_or(0x10);
+                    Self::print_memory(chip8, address, len);
+                }
+                None => println!("Invalid address {address}"),
+            },
+            ("h" | "help", _) => Self::print_help(),
+            _ => println!("Unknown command {line:?}, type h for help"),
+        }
+    }
+
+    fn resume(&mut self, mode: RunMode, chip8: &Chip8) {
+        self.mode = mode;
+        self.resume_address = Some(chip8.pc());
+    }
+
+    fn stop(&mut self, chip8: &Chip8, reason: fmt::Arguments) {
+        println!("{reason}");
+        self.pause(chip8);
+    }
+
+    fn print_location(chip8: &Chip8) {
+        println!("{}", Self::format_line(chip8, chip8.pc()));
+    }
+
+    fn format_line(chip8: &Chip8, address: u16) -> String {
+        let ram = chip8.ram();
+        let index = address as usize;
+        if index + 1 >= MEMORY_SIZE {
+            return format!("{address:#05X}  ----");
+        }
+        let op = (ram[index] as u16) << 8 | ram[index + 1] as u16;
+        format!("{address:#05X}  {op:04X}  {}", mnemonic_at(ram, index))
+    }
+
+    pub fn print_registers(chip8: &Chip8) {
+        println!(
+            "PC {:#05X}  I {:#05X}  SP {:X}  DT {:02X}  ST {:02X}",
+            chip8.pc(),
+            chip8.i(),
+            chip8.sp(),
+            chip8.delay_timer(),
+            chip8.sound_timer()
+        );
+        for row in chip8.v().chunks(8).enumerate() {
+            let (row, registers) = row;
+            let registers: Vec<String> = registers
+                .iter()
+                .enumerate()
+                .map(|(index, value)| format!("V{:X} {value:02X}", row * 8 + index))
+                .collect();
+            println!("{}", registers.join("  "));
+        }
+        let stack: Vec<String> = chip8.stack()[..chip8.sp() as usize]
+            .iter()
+            .map(|address| format!("{address:#05X}"))
+            .collect();
+        println!("Stack [{}]", stack.join(", "));
+    }
+
+    fn print_disassembly(&self, chip8: &Chip8, address: u16) {
+        let start = address.saturating_sub(8);
+        for line_address in (start..address.saturating_add(12)).step_by(2) {
+            let marker = if line_address == chip8.pc() { '>' } else { ' ' };
+            let breakpoint = if self.breakpoints.contains(&line_address) {
+                '*'
+            } else {
+                ' '
+            };
+            println!(
+                "{marker}{breakpoint} {}",
+                Self::format_line(chip8, line_address)
+            );
+        }
+    }
+
+    fn print_memory(chip8: &Chip8, address: u16, len: usize) {
+        let start = (address as usize).min(MEMORY_SIZE);
+        let end = (start + len).min(MEMORY_SIZE);
+        for (row, bytes) in chip8.ram()[start..end].chunks(16).enumerate() {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+            println!("{:#05X}  {}", start + row * 16, bytes.join(" "));
+        }
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Chip8Hooks for Debugger {
+    fn before_execute(&mut self, chip8: &Chip8, address: u16) -> bool {
+        let resuming = self.resume_address.take() == Some(address);
+        match self.mode {
+            RunMode::Paused => return false,
+            RunMode::StepOver {
+                address: target,
+                sp,
+            } if !resuming && address == target && chip8.sp() == sp => {
+                self.pause(chip8);
+                return false;
+            }
+            _ => (),
+        }
+        // A waiting FX0A runs again and again, it only hits its breakpoint once
+        if !resuming && !chip8.is_waiting_for_key() && self.breakpoints.contains(&address) {
+            self.stop(chip8, format_args!("Breakpoint at {address:#05X}"));
+            return false;
+        }
+
+        self.watched_values.clear();
+        self.watched_values.extend(
+            self.watchpoints
+                .iter()
+                .map(|watchpoint| watchpoint.value(chip8)),
+        );
+        true
+    }
+
+    fn after_execute(&mut self, chip8: &Chip8) {
+        let mut watch_hit = false;
+        for (watchpoint, old_value) in self.watchpoints.iter().zip(&self.watched_values) {
+            let new_value = watchpoint.value(chip8);
+            if new_value != *old_value {
+                println!("Watchpoint {watchpoint} changed {old_value:#X} -> {new_value:#X}");
+                watch_hit = true;
+            }
+        }
+
+        let stop = match self.mode {
+            RunMode::Step => true,
+            RunMode::RunToReturn { sp } => chip8.sp() < sp,
+            _ => watch_hit,
+        };
+        if stop {
+            self.pause(chip8);
+        }
+    }
+}
+
+/// Reads debugger commands from stdin on a background thread.
+pub fn spawn_console() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn parse_address(address: &str) -> Option<u16> {
+    let address = address
+        .strip_prefix("0x")
+        .or_else(|| address.strip_prefix("0X"))
+        .unwrap_or(address);
+    u16::from_str_radix(address, 16)
+        .ok()
+        .filter(|address| (*address as usize) < MEMORY_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::PROGRAM_START;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn next_on_a_call_in_the_last_word_steps() {
+        let mut chip8 = Chip8::new(Quirks::default(), 0);
+        chip8.ram_mut()[0xFFFE] = 0x22;
+        chip8.set_pc(0xFFFE);
+        let mut debugger = Debugger::new();
+        debugger.execute_command("n", &chip8);
+        assert_eq!(debugger.mode, RunMode::Step);
+    }
+
+    #[test]
+    fn breakpoint_on_a_waiting_key_wait_fires_once() {
+        let mut chip8 = Chip8::new(Quirks::default(), 0);
+        chip8
+            .load_rom(&[
+                0xF0, 0x0A, // LD V0, K
+                0x12, 0x02, // JP 0x202
+            ])
+            .unwrap();
+        let mut debugger = Debugger::new();
+        debugger.execute_command("b 200", &chip8);
+        debugger.execute_command("c", &chip8);
+        chip8.run_frame_with_hooks(&mut debugger).unwrap();
+        assert!(chip8.is_waiting_for_key());
+        assert!(!debugger.is_paused());
+
+        debugger.execute_command("b 202", &chip8);
+        chip8.change_key_state(0x3, true);
+        chip8.change_key_state(0x3, false);
+        let result = chip8.run_frame_with_hooks(&mut debugger).unwrap();
+        assert!(result.stopped);
+        assert_eq!(chip8.pc(), PROGRAM_START + 2);
+        assert_eq!(chip8.v()[0], 0x3);
+    }
+}
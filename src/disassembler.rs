@@ -0,0 +1,209 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::chip8::PROGRAM_START;
+use crate::instruction::Instruction;
+
+/// Mnemonic for a single opcode, unknown opcodes are shown as data words.
+pub fn mnemonic(op: u16) -> String {
+    match Instruction::decode(op) {
+        Some(instruction) => instruction.to_string(),
+        None => format!("DW {op:#06X}"),
+    }
+}
+
+/// Mnemonic for the instruction at `offset` in `memory`, F000 NNNN also
+/// shows the address from the following word.
+pub fn mnemonic_at(memory: &[u8], offset: usize) -> String {
+    let op = read_op(memory, offset);
+    match Instruction::decode(op) {
+        Some(Instruction::LdILong) if offset + 3 < memory.len() => {
+            format!("LD I, LONG {:#06X}", read_op(memory, offset + 2))
+        }
+        _ => mnemonic(op),
+    }
+}
+
+/// Disassembles a rom loaded at `PROGRAM_START`.
+///
+/// Code is found by following jumps, calls and skips from the entry point,
+/// everything that isn't reached that way is listed as sprite data.
+pub fn disassemble(rom: &[u8]) -> String {
+    let mut instruction_starts = vec![false; rom.len()];
+    let mut subroutines = BTreeSet::new();
+    let mut jump_targets = BTreeSet::new();
+    let mut pending = vec![PROGRAM_START];
+
+    while let Some(address) = pending.pop() {
+        let Some(offset) = rom_offset(rom, address) else {
+            continue;
+        };
+        if instruction_starts[offset] {
+            continue;
+        }
+        let Some(instruction) = Instruction::decode(read_op(rom, offset)) else {
+            continue;
+        };
+        instruction_starts[offset] = true;
+
+        // Nothing follows an instruction in the last word of memory
+        let next = address.checked_add(instruction_len(instruction) as u16);
+        match instruction {
+            Instruction::Jp(target) => {
+                jump_targets.insert(target);
+                pending.push(target);
+            }
+            Instruction::Call(target) => {
+                subroutines.insert(target);
+                pending.push(target);
+                pending.extend(next);
+            }
+            Instruction::JpV0(target) => {
+                // The offset is only known at runtime, follow the base address
+                jump_targets.insert(target);
+                pending.push(target);
+            }
+            Instruction::Ret | Instruction::Exit => (),
+            Instruction::SeByte(..)
+            | Instruction::SneByte(..)
+            | Instruction::SeReg(..)
+            | Instruction::SneReg(..)
+            | Instruction::Skp(_)
+            | Instruction::Sknp(_) => {
+                let Some(next) = next else {
+                    continue;
+                };
+                pending.push(next);
+                let skipped_len = rom_offset(rom, next)
+                    .and_then(|offset| Instruction::decode(read_op(rom, offset)))
+                    .map_or(2, instruction_len);
+                pending.extend(next.checked_add(skipped_len as u16));
+            }
+            _ => pending.extend(next),
+        }
+    }
+
+    // The listing shows instructions whole, so starts that fall inside an
+    // earlier instruction never get a line of their own
+    let mut offset = 0;
+    while offset < rom.len() {
+        if instruction_starts[offset] {
+            let len = listed_len(rom, offset);
+            instruction_starts[offset + 1..offset + len].fill(false);
+            offset += len;
+        } else {
+            offset += 1;
+        }
+    }
+
+    // Targets outside the rom or inside another instruction keep their address
+    let label = |address: u16| {
+        if !rom_offset(rom, address).is_some_and(|offset| instruction_starts[offset]) {
+            None
+        } else if subroutines.contains(&address) {
+            Some(format!("sub_{address:03X}"))
+        } else if jump_targets.contains(&address) {
+            Some(format!("loc_{address:03X}"))
+        } else {
+            None
+        }
+    };
+
+    let mut listing = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = PROGRAM_START + offset as u16;
+        if let Some(label) = label(address) {
+            let _ = writeln!(listing, "{label}:");
+        }
+
+        if instruction_starts[offset] {
+            let op = read_op(rom, offset);
+            let text = match Instruction::decode(op) {
+                Some(Instruction::Jp(target)) => match label(target) {
+                    Some(label) => format!("JP {label}"),
+                    None => mnemonic(op),
+                },
+                Some(Instruction::Call(target)) => match label(target) {
+                    Some(label) => format!("CALL {label}"),
+                    None => mnemonic(op),
+                },
+                Some(Instruction::LdILong) if offset + 3 < rom.len() => {
+                    let target = read_op(rom, offset + 2);
+                    match label(target) {
+                        Some(label) => format!("LD I, LONG {label}"),
+                        None => mnemonic_at(rom, offset),
+                    }
+                }
+                _ => mnemonic(op),
+            };
+            let len = listed_len(rom, offset);
+            let bytes: Vec<String> = rom[offset..offset + len]
+                .iter()
+                .map(|byte| format!("{byte:02X}"))
+                .collect();
+            let _ = writeln!(listing, "{address:#05X}  {:<5}  {text}", bytes.join(" "));
+            offset += len;
+        } else {
+            let byte = rom[offset];
+            let pixels: String = (0..8)
+                .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                .collect();
+            let _ = writeln!(
+                listing,
+                "{address:#05X}  {byte:02X}     DB {byte:#04X}  ; {pixels}"
+            );
+            offset += 1;
+        }
+    }
+    listing
+}
+
+fn instruction_len(instruction: Instruction) -> usize {
+    match instruction {
+        Instruction::LdILong => 4,
+        _ => 2,
+    }
+}
+
+/// Bytes the listing shows for the instruction at `offset`, a LONG cut off
+/// by the end of the rom only gets its first word.
+fn listed_len(rom: &[u8], offset: usize) -> usize {
+    let len = Instruction::decode(read_op(rom, offset)).map_or(2, instruction_len);
+    if offset + len <= rom.len() {
+        len
+    } else {
+        2
+    }
+}
+
+fn rom_offset(rom: &[u8], address: u16) -> Option<usize> {
+    let offset = (address as usize).checked_sub(PROGRAM_START as usize)?;
+    (offset + 1 < rom.len()).then_some(offset)
+}
+
+fn read_op(rom: &[u8], offset: usize) -> u16 {
+    (rom[offset] as u16) << 8 | rom[offset + 1] as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_without_a_listed_instruction_keep_their_address() {
+        let listing = disassemble(&[
+            0x22, 0x08, // CALL 0x208
+            0x30, 0x00, // SE V0, 0
+            0x12, 0x0A, // JP 0x20A, the middle of the LONG below
+            0x1F, 0xFF, // JP 0xFFF, past the end of the rom
+            0xF0, 0x00, 0x12, 0x34, // LD I, LONG 0x1234
+            0x00, 0xEE, // RET
+        ]);
+        assert!(listing.contains("CALL sub_208"), "{listing}");
+        assert!(listing.contains("sub_208:\n0x208"), "{listing}");
+        assert!(listing.contains("JP 0x20A"), "{listing}");
+        assert!(listing.contains("JP 0xFFF"), "{listing}");
+        assert!(!listing.contains("loc_"), "{listing}");
+    }
+}
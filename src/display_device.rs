@@ -1,67 +1,254 @@
 use sdl2::pixels::{Color, PixelFormat, PixelFormatEnum};
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
 
 use crate::chip8;
+use crate::config::DisplayFilter;
+use crate::menu;
 
 pub struct DisplayDevice {
     canvas: Canvas<Window>,
-    background_color: Color,
-    sprite_color: Color,
+    texture_creator: TextureCreator<WindowContext>,
+    // One texel per pixel, recreated when the resolution changes
+    texture: Texture,
+    // Indexed by the plane bits of a pixel
+    palette: [Color; 4],
+    integer_scale: bool,
+    filter: DisplayFilter,
+    phosphor_decay: f32,
+    // Pixels and colors of the last frame drawn, the filters mix them into the next one
+    previous_pixels: Vec<u8>,
+    frame: Vec<[f32; 3]>,
+    // Menu drawn over the display while it's open
+    overlay: Texture,
+    show_overlay: bool,
 }
-const SCALE_X: u8 = 12;
-const SCALE_Y: u8 = 12;
 
 impl DisplayDevice {
-    pub fn new(sdl_context: &sdl2::Sdl, background_color: u32, sprite_color: u32) -> Self {
+    /// Opens a resizable window `scale` times the size of the 64x32 display,
+    /// the display keeps its aspect ratio whatever the window size.
+    pub fn new(sdl_context: &sdl2::Sdl, scale: u32, integer_scale: bool, vsync: bool) -> Self {
         let video_subsystem = sdl_context.video().unwrap();
         let window = video_subsystem
             .window(
                 "Chip-8",
-                chip8::DISPLAY_WIDTH as u32 * SCALE_X as u32,
-                chip8::DISPLAY_HEIGHT as u32 * SCALE_Y as u32,
+                chip8::DISPLAY_WIDTH as u32 * scale,
+                chip8::DISPLAY_HEIGHT as u32 * scale,
             )
             .position_centered()
+            .resizable()
             .build()
             .unwrap();
 
-        let mut canvas = window.into_canvas().build().unwrap();
-        let background_color = Color::from_u32(
-            &PixelFormat::try_from(PixelFormatEnum::RGB24).unwrap(),
-            background_color,
-        );
-        let sprite_color = Color::from_u32(
-            &PixelFormat::try_from(PixelFormatEnum::RGB24).unwrap(),
-            sprite_color,
-        );
-        canvas.set_draw_color(background_color);
+        let mut canvas_builder = window.into_canvas();
+        if vsync {
+            canvas_builder = canvas_builder.present_vsync();
+        }
+        let mut canvas = canvas_builder.build().unwrap();
+        canvas.set_draw_color(Color::BLACK);
         canvas.clear();
         canvas.present();
 
+        let texture_creator = canvas.texture_creator();
+        let texture = Self::create_texture(
+            &texture_creator,
+            chip8::DISPLAY_WIDTH,
+            chip8::DISPLAY_HEIGHT,
+        );
+        let mut overlay = texture_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGBA32,
+                menu::OVERLAY_WIDTH as u32,
+                menu::OVERLAY_HEIGHT as u32,
+            )
+            .unwrap();
+        overlay.set_blend_mode(BlendMode::Blend);
         DisplayDevice {
             canvas,
-            background_color,
-            sprite_color,
+            texture_creator,
+            texture,
+            palette: [Color::BLACK; 4],
+            integer_scale,
+            filter: DisplayFilter::None,
+            phosphor_decay: 0.0,
+            previous_pixels: Vec::new(),
+            frame: Vec::new(),
+            overlay,
+            show_overlay: false,
+        }
+    }
+
+    fn create_texture(
+        texture_creator: &TextureCreator<WindowContext>,
+        width: usize,
+        height: usize,
+    ) -> Texture {
+        texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+            .unwrap()
+    }
+
+    /// `palette` holds the background, first plane, second plane and
+    /// overlapping planes colors.
+    pub fn set_palette(&mut self, palette: [u32; 4]) {
+        let pixel_format = PixelFormat::try_from(PixelFormatEnum::RGB24).unwrap();
+        self.palette = palette.map(|color| Color::from_u32(&pixel_format, color));
+    }
+
+    /// `phosphor_decay` is the share of brightness a cleared pixel keeps each
+    /// frame with the phosphor filter.
+    pub fn set_filter(&mut self, filter: DisplayFilter, phosphor_decay: f32) {
+        self.filter = filter;
+        self.phosphor_decay = phosphor_decay.clamp(0.0, 1.0);
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        let window = self.canvas.window_mut();
+        if window.title() != title {
+            let _ = window.set_title(title);
+        }
+    }
+
+    /// Resizes the window to `scale` times the size of the 64x32 display.
+    pub fn set_scale(&mut self, scale: u32) {
+        let window = self.canvas.window_mut();
+        let (width, height) = (
+            chip8::DISPLAY_WIDTH as u32 * scale,
+            chip8::DISPLAY_HEIGHT as u32 * scale,
+        );
+        if let Err(error) = window.set_size(width, height) {
+            eprintln!("Couldn't resize the window: {error}");
+        }
+    }
+
+    /// Shows a bitmap of `menu::OVERLAY_*` values over the display, or hides it.
+    pub fn set_overlay(&mut self, overlay: Option<&[u8]>) {
+        self.show_overlay = overlay.is_some();
+        let Some(overlay) = overlay else {
+            return;
+        };
+        self.overlay
+            .with_lock(None, |buffer, pitch| {
+                for (row, line) in overlay.chunks_exact(menu::OVERLAY_WIDTH).enumerate() {
+                    let texels = &mut buffer[row * pitch..row * pitch + menu::OVERLAY_WIDTH * 4];
+                    for (texel, pixel) in texels.chunks_exact_mut(4).zip(line) {
+                        texel.copy_from_slice(match *pixel {
+                            menu::OVERLAY_TEXT | menu::OVERLAY_HIGHLIGHT => {
+                                &[0xFF, 0xFF, 0xFF, 0xFF]
+                            }
+                            menu::OVERLAY_HIGHLIGHTED_TEXT => &[0x00, 0x00, 0x00, 0xFF],
+                            // Dark enough to read over, light enough to see the game
+                            _ => &[0x00, 0x00, 0x00, 0xC8],
+                        });
+                    }
+                }
+            })
+            .unwrap();
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(error) = window.set_fullscreen(fullscreen) {
+            eprintln!("Couldn't toggle fullscreen: {error}");
         }
     }
 
-    pub fn draw(&mut self, pixels: &[bool; chip8::DISPLAY_WIDTH * chip8::DISPLAY_HEIGHT]) {
-        self.canvas.set_draw_color(self.background_color);
+    pub fn draw(&mut self, pixels: &[u8], width: usize, height: usize) {
+        debug_assert_eq!(pixels.len(), width * height);
+        let query = self.texture.query();
+        if (query.width as usize, query.height as usize) != (width, height) {
+            let texture = Self::create_texture(&self.texture_creator, width, height);
+            let old_texture = std::mem::replace(&mut self.texture, texture);
+            // Textures aren't freed on drop with unsafe_textures.
+            // SAFETY: the texture belongs to the renderer of `canvas`, which
+            // lives as long as `self`, and isn't used again after this.
+            unsafe { old_texture.destroy() };
+        }
+        self.update_scaling(width, height);
+        self.apply_filter(pixels);
+
+        let frame = &self.frame;
+        self.texture
+            .with_lock(None, |buffer, pitch| {
+                for (row, line) in frame.chunks_exact(width).enumerate() {
+                    let texels = &mut buffer[row * pitch..row * pitch + width * 3];
+                    for (texel, color) in texels.chunks_exact_mut(3).zip(line) {
+                        texel.copy_from_slice(&color.map(|channel| channel.round() as u8));
+                    }
+                }
+            })
+            .unwrap();
+
+        // Letterbox bars stay black, the texture is stretched over the display
+        self.canvas.set_draw_color(Color::BLACK);
         self.canvas.clear();
-        self.canvas.set_draw_color(self.sprite_color);
-        for (index, pixel) in pixels.iter().enumerate() {
-            if *pixel {
-                let x = index % chip8::DISPLAY_WIDTH;
-                let y = index / chip8::DISPLAY_WIDTH;
-                let _ = self.canvas.fill_rect(Rect::new(
-                    x as i32 * SCALE_X as i32,
-                    y as i32 * SCALE_Y as i32,
-                    SCALE_X as u32,
-                    SCALE_Y as u32,
-                ));
-            }
+        let _ = self.canvas.copy(&self.texture, None, None);
+        if self.show_overlay {
+            let _ = self.canvas.copy(&self.overlay, None, None);
         }
         self.canvas.present();
     }
+
+    /// Every mode is 2:1, SDL scales and letterboxes the display to the window.
+    /// The logical size follows the resolution so integer scaling uses whole
+    /// multiples of the pixels actually shown.
+    fn update_scaling(&mut self, width: usize, height: usize) {
+        let (width, height) = (width as u32, height as u32);
+        if self.canvas.logical_size() != (width, height) {
+            let _ = self.canvas.set_logical_size(width, height);
+        }
+        // SDL never scales below 1x, which would crop a display bigger than the window
+        let (window_width, window_height) = self.canvas.output_size().unwrap_or((width, height));
+        let integer_scale = self.integer_scale && window_width >= width && window_height >= height;
+        if self.canvas.integer_scale() != integer_scale {
+            let _ = self.canvas.set_integer_scale(integer_scale);
+        }
+    }
+
+    /// Turns `pixels` into the colors to show in `frame`.
+    fn apply_filter(&mut self, pixels: &[u8]) {
+        // Nothing to mix with after a resolution change
+        if self.previous_pixels.len() != pixels.len() {
+            self.previous_pixels = pixels.to_vec();
+            self.frame = vec![[0.0; 3]; pixels.len()];
+        }
+
+        let color = |pixel: u8| {
+            let color = self.palette[pixel as usize & 0x3];
+            [color.r, color.g, color.b].map(f32::from)
+        };
+        let mix = |a: [f32; 3], b: [f32; 3], b_share: f32| {
+            [0, 1, 2].map(|channel| a[channel] * (1.0 - b_share) + b[channel] * b_share)
+        };
+        for ((shown, pixel), previous) in
+            self.frame.iter_mut().zip(pixels).zip(&self.previous_pixels)
+        {
+            let target = color(*pixel);
+            *shown = match self.filter {
+                DisplayFilter::None => target,
+                DisplayFilter::Phosphor if *pixel == 0 => mix(target, *shown, self.phosphor_decay),
+                DisplayFilter::Phosphor => target,
+                DisplayFilter::Blend => mix(target, color(*previous), 0.5),
+                DisplayFilter::Ghosting if *pixel == 0 => color(*previous),
+                DisplayFilter::Ghosting => target,
+            };
+        }
+        self.previous_pixels.copy_from_slice(pixels);
+    }
+}
+
+impl Drop for DisplayDevice {
+    fn drop(&mut self) {
+        // SAFETY: the fields, `canvas` and its renderer among them, are only
+        // dropped after this runs. `Texture` has no drop glue with
+        // unsafe_textures, so the copies read here are the only ones destroyed.
+        unsafe {
+            std::ptr::read(&self.texture).destroy();
+            std::ptr::read(&self.overlay).destroy();
+        }
+    }
 }
@@ -0,0 +1,34 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// A keymap, rom database or movie file that couldn't be read, parsed or
+/// written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileError {
+    pub path: String,
+    pub message: String,
+}
+
+impl FileError {
+    pub fn new(path: &Path, message: impl Into<String>) -> Self {
+        FileError {
+            path: path.display().to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for FileError {}
+
+/// Reads the text file at `path` and parses it with `parse`.
+pub fn load<T>(path: &Path, parse: impl FnOnce(&str) -> Result<T, String>) -> Result<T, FileError> {
+    let text = fs::read_to_string(path).map_err(|error| FileError::new(path, error.to_string()))?;
+    parse(&text).map_err(|message| FileError::new(path, message))
+}
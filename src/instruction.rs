@@ -0,0 +1,234 @@
+use std::fmt;
+
+/// A decoded instruction, mnemonics follow Cowgod's technical reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// NOP
+    Nop,
+    /// CLS
+    Cls,
+    /// RET
+    Ret,
+    /// SCD nibble
+    Scd(u8),
+    /// SCU nibble
+    Scu(u8),
+    /// SCR
+    Scr,
+    /// SCL
+    Scl,
+    /// EXIT
+    Exit,
+    /// LOW
+    Low,
+    /// HIGH
+    High,
+    /// JP addr
+    Jp(u16),
+    /// CALL addr
+    Call(u16),
+    /// SE Vx, byte
+    SeByte(u8, u8),
+    /// SNE Vx, byte
+    SneByte(u8, u8),
+    /// SE Vx, Vy
+    SeReg(u8, u8),
+    /// SAVE Vx, Vy
+    SaveRange(u8, u8),
+    /// LOAD Vx, Vy
+    LoadRange(u8, u8),
+    /// LD Vx, byte
+    LdByte(u8, u8),
+    /// ADD Vx, byte
+    AddByte(u8, u8),
+    /// LD Vx, Vy
+    LdReg(u8, u8),
+    /// OR Vx, Vy
+    Or(u8, u8),
+    /// AND Vx, Vy
+    And(u8, u8),
+    /// XOR Vx, Vy
+    Xor(u8, u8),
+    /// ADD Vx, Vy
+    AddReg(u8, u8),
+    /// SUB Vx, Vy
+    Sub(u8, u8),
+    /// SHR Vx {, Vy}
+    Shr(u8, u8),
+    /// SUBN Vx, Vy
+    Subn(u8, u8),
+    /// SHL Vx {, Vy}
+    Shl(u8, u8),
+    /// SNE Vx, Vy
+    SneReg(u8, u8),
+    /// LD I, addr
+    LdI(u16),
+    /// JP V0, addr
+    JpV0(u16),
+    /// RND Vx, byte
+    Rnd(u8, u8),
+    /// DRW Vx, Vy, nibble
+    Drw(u8, u8, u8),
+    /// SKP Vx
+    Skp(u8),
+    /// SKNP Vx
+    Sknp(u8),
+    /// LD I, LONG addr, the address is the following word
+    LdILong,
+    /// PLANE n
+    Plane(u8),
+    /// AUDIO
+    Audio,
+    /// LD Vx, DT
+    LdVxDt(u8),
+    /// LD Vx, K
+    LdVxK(u8),
+    /// LD DT, Vx
+    LdDtVx(u8),
+    /// LD ST, Vx
+    LdStVx(u8),
+    /// ADD I, Vx
+    AddI(u8),
+    /// LD F, Vx
+    LdF(u8),
+    /// LD HF, Vx
+    LdHf(u8),
+    /// LD B, Vx
+    LdB(u8),
+    /// PITCH Vx
+    Pitch(u8),
+    /// LD [I], Vx
+    LdMemVx(u8),
+    /// LD Vx, [I]
+    LdVxMem(u8),
+    /// LD R, Vx
+    LdRVx(u8),
+    /// LD Vx, R
+    LdVxR(u8),
+}
+
+impl Instruction {
+    /// Decodes an opcode, returns `None` for opcodes this interpreter doesn't know.
+    pub fn decode(op: u16) -> Option<Self> {
+        let first_nibble = ((op & 0xF000) >> 12) as u8;
+        let x = ((op & 0x0F00) >> 8) as u8;
+        let y = ((op & 0x00F0) >> 4) as u8;
+        let n = (op & 0x000F) as u8;
+        let nn = (op & 0x00FF) as u8;
+        let nnn = op & 0x0FFF;
+
+        let instruction = match (first_nibble, x, y, n) {
+            (0x0, 0x0, 0x0, 0x0) => Instruction::Nop,
+            (0x0, 0x0, 0xE, 0x0) => Instruction::Cls,
+            (0x0, 0x0, 0xE, 0xE) => Instruction::Ret,
+            (0x0, 0x0, 0xC, _) => Instruction::Scd(n),
+            (0x0, 0x0, 0xD, _) => Instruction::Scu(n),
+            (0x0, 0x0, 0xF, 0xB) => Instruction::Scr,
+            (0x0, 0x0, 0xF, 0xC) => Instruction::Scl,
+            (0x0, 0x0, 0xF, 0xD) => Instruction::Exit,
+            (0x0, 0x0, 0xF, 0xE) => Instruction::Low,
+            (0x0, 0x0, 0xF, 0xF) => Instruction::High,
+            (0x1, _, _, _) => Instruction::Jp(nnn),
+            (0x2, _, _, _) => Instruction::Call(nnn),
+            (0x3, _, _, _) => Instruction::SeByte(x, nn),
+            (0x4, _, _, _) => Instruction::SneByte(x, nn),
+            (0x5, _, _, 0x0) => Instruction::SeReg(x, y),
+            (0x5, _, _, 0x2) => Instruction::SaveRange(x, y),
+            (0x5, _, _, 0x3) => Instruction::LoadRange(x, y),
+            (0x6, _, _, _) => Instruction::LdByte(x, nn),
+            (0x7, _, _, _) => Instruction::AddByte(x, nn),
+            (0x8, _, _, 0x0) => Instruction::LdReg(x, y),
+            (0x8, _, _, 0x1) => Instruction::Or(x, y),
+            (0x8, _, _, 0x2) => Instruction::And(x, y),
+            (0x8, _, _, 0x3) => Instruction::Xor(x, y),
+            (0x8, _, _, 0x4) => Instruction::AddReg(x, y),
+            (0x8, _, _, 0x5) => Instruction::Sub(x, y),
+            (0x8, _, _, 0x6) => Instruction::Shr(x, y),
+            (0x8, _, _, 0x7) => Instruction::Subn(x, y),
+            (0x8, _, _, 0xE) => Instruction::Shl(x, y),
+            (0x9, _, _, 0x0) => Instruction::SneReg(x, y),
+            (0xA, _, _, _) => Instruction::LdI(nnn),
+            (0xB, _, _, _) => Instruction::JpV0(nnn),
+            (0xC, _, _, _) => Instruction::Rnd(x, nn),
+            (0xD, _, _, _) => Instruction::Drw(x, y, n),
+            (0xE, _, 0x9, 0xE) => Instruction::Skp(x),
+            (0xE, _, 0xA, 0x1) => Instruction::Sknp(x),
+            (0xF, 0x0, 0x0, 0x0) => Instruction::LdILong,
+            (0xF, _, 0x0, 0x1) => Instruction::Plane(x),
+            (0xF, 0x0, 0x0, 0x2) => Instruction::Audio,
+            (0xF, _, 0x0, 0x7) => Instruction::LdVxDt(x),
+            (0xF, _, 0x0, 0xA) => Instruction::LdVxK(x),
+            (0xF, _, 0x1, 0x5) => Instruction::LdDtVx(x),
+            (0xF, _, 0x1, 0x8) => Instruction::LdStVx(x),
+            (0xF, _, 0x1, 0xE) => Instruction::AddI(x),
+            (0xF, _, 0x2, 0x9) => Instruction::LdF(x),
+            (0xF, _, 0x3, 0x0) => Instruction::LdHf(x),
+            (0xF, _, 0x3, 0x3) => Instruction::LdB(x),
+            (0xF, _, 0x3, 0xA) => Instruction::Pitch(x),
+            (0xF, _, 0x5, 0x5) => Instruction::LdMemVx(x),
+            (0xF, _, 0x6, 0x5) => Instruction::LdVxMem(x),
+            (0xF, _, 0x7, 0x5) => Instruction::LdRVx(x),
+            (0xF, _, 0x8, 0x5) => Instruction::LdVxR(x),
+            (_, _, _, _) => return None,
+        };
+        Some(instruction)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Nop => write!(f, "NOP"),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Scd(n) => write!(f, "SCD {n:#X}"),
+            Instruction::Scu(n) => write!(f, "SCU {n:#X}"),
+            Instruction::Scr => write!(f, "SCR"),
+            Instruction::Scl => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jp(nnn) => write!(f, "JP {nnn:#05X}"),
+            Instruction::Call(nnn) => write!(f, "CALL {nnn:#05X}"),
+            Instruction::SeByte(x, nn) => write!(f, "SE V{x:X}, {nn:#04X}"),
+            Instruction::SneByte(x, nn) => write!(f, "SNE V{x:X}, {nn:#04X}"),
+            Instruction::SeReg(x, y) => write!(f, "SE V{x:X}, V{y:X}"),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{x:X}, V{y:X}"),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{x:X}, V{y:X}"),
+            Instruction::LdByte(x, nn) => write!(f, "LD V{x:X}, {nn:#04X}"),
+            Instruction::AddByte(x, nn) => write!(f, "ADD V{x:X}, {nn:#04X}"),
+            Instruction::LdReg(x, y) => write!(f, "LD V{x:X}, V{y:X}"),
+            Instruction::Or(x, y) => write!(f, "OR V{x:X}, V{y:X}"),
+            Instruction::And(x, y) => write!(f, "AND V{x:X}, V{y:X}"),
+            Instruction::Xor(x, y) => write!(f, "XOR V{x:X}, V{y:X}"),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{x:X}, V{y:X}"),
+            Instruction::Sub(x, y) => write!(f, "SUB V{x:X}, V{y:X}"),
+            Instruction::Shr(x, y) => write!(f, "SHR V{x:X}, V{y:X}"),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Instruction::Shl(x, y) => write!(f, "SHL V{x:X}, V{y:X}"),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{x:X}, V{y:X}"),
+            Instruction::LdI(nnn) => write!(f, "LD I, {nnn:#05X}"),
+            Instruction::JpV0(nnn) => write!(f, "JP V0, {nnn:#05X}"),
+            Instruction::Rnd(x, nn) => write!(f, "RND V{x:X}, {nn:#04X}"),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{x:X}, V{y:X}, {n:#X}"),
+            Instruction::Skp(x) => write!(f, "SKP V{x:X}"),
+            Instruction::Sknp(x) => write!(f, "SKNP V{x:X}"),
+            Instruction::LdILong => write!(f, "LD I, LONG"),
+            Instruction::Plane(n) => write!(f, "PLANE {n:#X}"),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LdVxDt(x) => write!(f, "LD V{x:X}, DT"),
+            Instruction::LdVxK(x) => write!(f, "LD V{x:X}, K"),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{x:X}"),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{x:X}"),
+            Instruction::AddI(x) => write!(f, "ADD I, V{x:X}"),
+            Instruction::LdF(x) => write!(f, "LD F, V{x:X}"),
+            Instruction::LdHf(x) => write!(f, "LD HF, V{x:X}"),
+            Instruction::LdB(x) => write!(f, "LD B, V{x:X}"),
+            Instruction::Pitch(x) => write!(f, "PITCH V{x:X}"),
+            Instruction::LdMemVx(x) => write!(f, "LD [I], V{x:X}"),
+            Instruction::LdVxMem(x) => write!(f, "LD V{x:X}, [I]"),
+            Instruction::LdRVx(x) => write!(f, "LD R, V{x:X}"),
+            Instruction::LdVxR(x) => write!(f, "LD V{x:X}, R"),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::file_error::{self, FileError};
+
+/// The 1234/QWER/ASDF/ZXCV layout, keypad keys in the same positions.
+const DEFAULT_KEYS: [(&str, u8); 16] = [
+    ("1", 0x1),
+    ("2", 0x2),
+    ("3", 0x3),
+    ("4", 0xC),
+    ("Q", 0x4),
+    ("W", 0x5),
+    ("E", 0x6),
+    ("R", 0xD),
+    ("A", 0x7),
+    ("S", 0x8),
+    ("D", 0x9),
+    ("F", 0xE),
+    ("Z", 0xA),
+    ("X", 0x0),
+    ("C", 0xB),
+    ("V", 0xF),
+];
+
+/// Game controller inputs in the cross layout most roms steer with.
+const DEFAULT_BUTTONS: [(&str, u8); 10] = [
+    ("dpup", 0x2),
+    ("dpleft", 0x4),
+    ("dpright", 0x6),
+    ("dpdown", 0x8),
+    ("lefty-", 0x2),
+    ("leftx-", 0x4),
+    ("leftx+", 0x6),
+    ("lefty+", 0x8),
+    ("a", 0x5),
+    ("b", 0x0),
+];
+
+/// A parsed keymap file.
+///
+/// Its `[keys]` and `[buttons]` tables replace the default layouts, and
+/// `[roms."<file name>"]` tables add or change keys and buttons for a single
+/// rom:
+///
+/// ```toml
+/// [keys]
+/// "1" = 0x1
+/// A = 0x4
+///
+/// [buttons]
+/// dpleft = 0x4
+/// "leftx-" = 0x4
+///
+/// [roms."brix.ch8"]
+/// Left = 0x4
+/// Right = 0x6
+///
+/// [roms."brix.ch8".buttons]
+/// x = 0x5
+/// ```
+///
+/// Keys use SDL's key names and buttons SDL's game controller names, stick
+/// and trigger directions are the axis name followed by `-` or `+`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeymapFile {
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+    #[serde(default)]
+    buttons: BTreeMap<String, u8>,
+    #[serde(default)]
+    roms: BTreeMap<String, RomOverrides>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct RomOverrides {
+    #[serde(default)]
+    buttons: BTreeMap<String, u8>,
+    #[serde(flatten)]
+    keys: BTreeMap<String, u8>,
+}
+
+impl KeymapFile {
+    pub fn load(path: &Path) -> Result<Self, FileError> {
+        file_error::load(path, Self::parse)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: KeymapFile = toml::from_str(text).map_err(|error| error.message().to_string())?;
+        let tables = [&file.keys, &file.buttons].into_iter().chain(
+            file.roms
+                .values()
+                .flat_map(|overrides| [&overrides.keys, &overrides.buttons]),
+        );
+        for table in tables {
+            check_keypad_keys(table)?;
+        }
+        Ok(file)
+    }
+
+    /// The mapping for the rom at `rom_path`.
+    pub fn keymap(&self, rom_path: &str) -> Keymap {
+        let mut keymap = Keymap::default();
+        if !self.keys.is_empty() {
+            keymap.keys.clear();
+        }
+        if !self.buttons.is_empty() {
+            keymap.buttons.clear();
+        }
+        keymap.extend(&self.keys, &self.buttons);
+
+        let rom_name = Path::new(rom_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        if let Some(overrides) = rom_name.and_then(|name| self.roms.get(&name)) {
+            keymap.extend(&overrides.keys, &overrides.buttons);
+        }
+        keymap
+    }
+}
+
+/// Maps SDL key names and game controller inputs to keys of the hex keypad.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: BTreeMap<String, u8>,
+    buttons: BTreeMap<String, u8>,
+}
+
+impl Keymap {
+    /// Adds or changes keys and buttons, fails if one maps past key 0xF.
+    pub fn with_overrides(
+        mut self,
+        keys: &BTreeMap<String, u8>,
+        buttons: &BTreeMap<String, u8>,
+    ) -> Result<Self, String> {
+        check_keypad_keys(keys)?;
+        check_keypad_keys(buttons)?;
+        self.extend(keys, buttons);
+        Ok(self)
+    }
+
+    fn extend(&mut self, keys: &BTreeMap<String, u8>, buttons: &BTreeMap<String, u8>) {
+        extend(&mut self.keys, keys);
+        extend(&mut self.buttons, buttons);
+    }
+
+    /// Makes the key called `name` the only one pressing `chip8_key`.
+    pub fn bind_key(&mut self, name: &str, chip8_key: u8) {
+        self.keys.retain(|_, key| *key != chip8_key);
+        extend(
+            &mut self.keys,
+            &BTreeMap::from([(name.to_string(), chip8_key)]),
+        );
+    }
+
+    /// Key names and the keypad keys they press.
+    pub fn keys(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.keys.iter().map(|(name, key)| (name.as_str(), *key))
+    }
+
+    /// Game controller input names and the keypad keys they press.
+    pub fn buttons(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.buttons.iter().map(|(name, key)| (name.as_str(), *key))
+    }
+
+    /// The mapping in keymap file format, ordered by keypad key.
+    pub fn to_toml(&self) -> String {
+        let mut text = String::new();
+        write_table(&mut text, "keys", &self.keys);
+        text.push('\n');
+        write_table(&mut text, "buttons", &self.buttons);
+        text
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let table = |entries: &[(&str, u8)]| {
+            entries
+                .iter()
+                .map(|(name, key)| (name.to_string(), *key))
+                .collect()
+        };
+        Keymap {
+            keys: table(&DEFAULT_KEYS),
+            buttons: table(&DEFAULT_BUTTONS),
+        }
+    }
+}
+
+fn check_keypad_keys(entries: &BTreeMap<String, u8>) -> Result<(), String> {
+    match entries.iter().find(|(_, key)| **key > 0xF) {
+        Some((name, key)) => Err(format!("{name} maps to {key:#X}, keypad keys go up to 0xF")),
+        None => Ok(()),
+    }
+}
+
+fn extend(table: &mut BTreeMap<String, u8>, entries: &BTreeMap<String, u8>) {
+    for (name, key) in entries {
+        // Later tables replace an entry whatever case it was written in
+        table.retain(|existing, _| !existing.eq_ignore_ascii_case(name));
+        table.insert(name.clone(), *key);
+    }
+}
+
+fn write_table(text: &mut String, table_name: &str, table: &BTreeMap<String, u8>) {
+    let mut entries: Vec<(&String, &u8)> = table.iter().collect();
+    entries.sort_by_key(|(name, key)| (**key, name.as_str()));
+
+    text.push_str(&format!("[{table_name}]\n"));
+    for (name, key) in entries {
+        text.push_str(&format!("{name:?} = {key:#X}\n"));
+    }
+}
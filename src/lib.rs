@@ -0,0 +1,31 @@
+pub mod assembler;
+#[cfg(feature = "sdl")]
+pub mod audio_device;
+pub mod chip8;
+pub mod config;
+#[cfg(feature = "sdl")]
+pub mod controller_device;
+pub mod debugger;
+pub mod disassembler;
+#[cfg(feature = "sdl")]
+pub mod display_device;
+pub mod file_error;
+pub mod instruction;
+pub mod keymap;
+pub mod menu;
+pub mod movie;
+#[cfg(feature = "sdl")]
+pub mod platform;
+pub mod quirks;
+pub mod rewind;
+pub mod rom_database;
+pub mod rom_watcher;
+pub mod save_state;
+pub mod timing;
+
+pub use chip8::{
+    Chip8, Chip8Error, Chip8Hooks, Chip8TickResult, DISPLAY_HEIGHT, DISPLAY_WIDTH, FRAME_DURATION,
+    HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH,
+};
+pub use quirks::Quirks;
+pub use timing::TimingModel;
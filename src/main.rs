@@ -1,15 +1,106 @@
-mod chip8;
-mod config;
-mod display_device;
-mod platform;
+use std::fs;
+use std::path::Path;
 
 use clap::Parser;
-use config::Config;
-use platform::Platform;
+use toy_chip_8::config::{Command, Config, Settings};
+use toy_chip_8::keymap::KeymapFile;
+use toy_chip_8::movie::Movie;
+use toy_chip_8::platform::Platform;
+use toy_chip_8::rom_database::RomDatabase;
+use toy_chip_8::{assembler, disassembler};
 
 fn main() {
-    let config = Config::parse();
-    let mut platform = Platform::new(config);
+    let mut config = Config::parse();
 
-    platform.start();
+    match config.command.take() {
+        Some(Command::Disasm { rom_path }) => disasm(&rom_path),
+        Some(Command::Asm {
+            source_path,
+            output,
+            run: run_rom,
+        }) => {
+            let output = output.unwrap_or_else(|| {
+                Path::new(&source_path)
+                    .with_extension("ch8")
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            asm(&source_path, &output);
+            if run_rom {
+                run(Config::for_rom(&output));
+            }
+        }
+        None => run(config),
+    }
+}
+
+fn run(config: Config) {
+    let keymap_file = config.keymap.as_ref().map(|keymap_path| {
+        KeymapFile::load(Path::new(keymap_path)).unwrap_or_else(|error| {
+            eprintln!("Couldn't load keymap {error}");
+            std::process::exit(1);
+        })
+    });
+    let rom_database = match &config.rom_database {
+        Some(database_path) => {
+            RomDatabase::load(Path::new(database_path)).unwrap_or_else(|error| {
+                eprintln!("Couldn't load rom database {error}");
+                std::process::exit(1);
+            })
+        }
+        None => RomDatabase::default(),
+    };
+    let replay = config.replay.as_ref().map(|movie_path| {
+        Movie::load(Path::new(movie_path)).unwrap_or_else(|error| {
+            eprintln!("Couldn't load movie {error}");
+            std::process::exit(1);
+        })
+    });
+    let settings = Settings {
+        config,
+        keymap_file,
+        rom_database,
+        replay,
+    };
+
+    if settings.config.print_keymap {
+        let rom_path = settings.config.rom_path.as_deref().unwrap_or_default();
+        let rom_data = fs::read(rom_path).unwrap_or_else(|error| {
+            eprintln!("Couldn't read rom file {rom_path}: {error}");
+            std::process::exit(1);
+        });
+        print!("{}", settings.for_rom(rom_path, &rom_data).keymap.to_toml());
+        return;
+    }
+
+    let result = Platform::new(settings).and_then(|mut platform| platform.start());
+
+    if let Err(error) = result {
+        Platform::show_fault(&error);
+        std::process::exit(1);
+    }
+}
+
+fn disasm(rom_path: &str) {
+    match fs::read(rom_path) {
+        Ok(rom_data) => print!("{}", disassembler::disassemble(&rom_data)),
+        Err(error) => {
+            eprintln!("Couldn't read rom file {rom_path}: {error}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn asm(source_path: &str, output: &str) {
+    let rom_data = match assembler::assemble_file(Path::new(source_path)) {
+        Ok(rom_data) => rom_data,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+    if let Err(error) = fs::write(output, rom_data) {
+        eprintln!("Couldn't write rom file {output}: {error}");
+        std::process::exit(1);
+    }
 }
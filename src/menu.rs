@@ -0,0 +1,461 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::FONT;
+use crate::keymap::Keymap;
+use crate::timing::TimingModel;
+
+/// Size of the bitmap `Menu::render` draws, twice the hi-res display.
+pub const OVERLAY_WIDTH: usize = 256;
+pub const OVERLAY_HEIGHT: usize = 128;
+
+/// Values of overlay pixels.
+pub const OVERLAY_SHADE: u8 = 0;
+pub const OVERLAY_TEXT: u8 = 1;
+pub const OVERLAY_HIGHLIGHT: u8 = 2;
+pub const OVERLAY_HIGHLIGHTED_TEXT: u8 = 3;
+
+/// Palettes to pick from besides the one the rom was started with, in
+/// background, first plane, second plane and overlapping planes order.
+pub const PALETTES: [(&str, [u32; 4]); 4] = [
+    ("GREEN", [0x0A140A, 0x33FF66, 0x1E8C3C, 0x9CFFB5]),
+    ("AMBER", [0x140C00, 0xFFB000, 0x996A00, 0xFFD966]),
+    ("LCD", [0x9BBC0F, 0x0F380F, 0x8BAC0F, 0x306230]),
+    ("INVERTED", [0xFFFFFF, 0x000000, 0x555555, 0xAAAAAA]),
+];
+
+const MARGIN: usize = 3;
+// FONT glyphs are 4x5, one pixel apart horizontally and two vertically
+const CHARACTER_WIDTH: usize = 5;
+const LINE_HEIGHT: usize = 7;
+const COLUMNS: usize = (OVERLAY_WIDTH - 2 * MARGIN) / CHARACTER_WIDTH;
+const FIRST_ITEM_Y: usize = MARGIN + 2 * LINE_HEIGHT;
+const VISIBLE_ITEMS: usize = (OVERLAY_HEIGHT - FIRST_ITEM_Y - MARGIN) / LINE_HEIGHT;
+
+/// Glyphs for the characters `FONT` doesn't have, in the same 4x5 format.
+const EXTRA_GLYPHS: [(char, [u8; 5]); 39] = [
+    ('G', [0xF0, 0x80, 0xB0, 0x90, 0xF0]),
+    ('H', [0x90, 0x90, 0xF0, 0x90, 0x90]),
+    ('I', [0xE0, 0x40, 0x40, 0x40, 0xE0]),
+    ('J', [0x30, 0x10, 0x10, 0x90, 0xF0]),
+    ('K', [0x90, 0xA0, 0xC0, 0xA0, 0x90]),
+    ('L', [0x80, 0x80, 0x80, 0x80, 0xF0]),
+    ('M', [0x90, 0xF0, 0xF0, 0x90, 0x90]),
+    ('N', [0x90, 0xD0, 0xF0, 0xB0, 0x90]),
+    ('O', [0x60, 0x90, 0x90, 0x90, 0x60]),
+    ('P', [0xF0, 0x90, 0xF0, 0x80, 0x80]),
+    ('Q', [0x60, 0x90, 0x90, 0xB0, 0x70]),
+    ('R', [0xE0, 0x90, 0xE0, 0xA0, 0x90]),
+    ('S', [0x70, 0x80, 0x60, 0x10, 0xE0]),
+    ('T', [0xE0, 0x40, 0x40, 0x40, 0x40]),
+    ('U', [0x90, 0x90, 0x90, 0x90, 0x60]),
+    ('V', [0x90, 0x90, 0xA0, 0xA0, 0x40]),
+    ('W', [0x90, 0x90, 0xF0, 0xF0, 0x90]),
+    ('X', [0x90, 0x90, 0x60, 0x90, 0x90]),
+    ('Y', [0xA0, 0xA0, 0x40, 0x40, 0x40]),
+    ('Z', [0xF0, 0x10, 0x60, 0x80, 0xF0]),
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x40]),
+    (',', [0x00, 0x00, 0x00, 0x40, 0x80]),
+    (':', [0x00, 0x40, 0x00, 0x40, 0x00]),
+    ('-', [0x00, 0x00, 0xE0, 0x00, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0xF0]),
+    ('+', [0x00, 0x40, 0xE0, 0x40, 0x00]),
+    ('=', [0x00, 0xE0, 0x00, 0xE0, 0x00]),
+    ('<', [0x20, 0x40, 0x80, 0x40, 0x20]),
+    ('>', [0x80, 0x40, 0x20, 0x40, 0x80]),
+    ('/', [0x10, 0x10, 0x20, 0x40, 0x80]),
+    ('%', [0x90, 0x10, 0x20, 0x40, 0x90]),
+    ('(', [0x20, 0x40, 0x40, 0x40, 0x20]),
+    (')', [0x40, 0x20, 0x20, 0x20, 0x40]),
+    ('[', [0x60, 0x40, 0x40, 0x40, 0x60]),
+    (']', [0x60, 0x20, 0x20, 0x20, 0x60]),
+    ('!', [0x40, 0x40, 0x40, 0x00, 0x40]),
+    ('\'', [0x40, 0x40, 0x00, 0x00, 0x00]),
+    ('?', [0xE0, 0x10, 0x60, 0x00, 0x40]),
+];
+
+/// Input the menu understands, the platform maps keys to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+}
+
+/// Something the platform should do for the menu.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MenuAction {
+    Close,
+    Palette { forward: bool },
+    Slower,
+    Faster,
+    NextTiming,
+    Quirks { forward: bool },
+    Volume { louder: bool },
+    ToggleMute,
+    Scale { larger: bool },
+    SaveSlot { forward: bool },
+    SaveState,
+    LoadState,
+    BindKey { key_name: String, chip8_key: u8 },
+    OpenRom(PathBuf),
+}
+
+/// Current settings for the menu to show.
+pub struct MenuStatus<'a> {
+    pub palette: &'a str,
+    pub timing_model: TimingModel,
+    pub quirks: &'a str,
+    pub volume: f32,
+    pub muted: bool,
+    pub scale: u32,
+    pub save_slot: u8,
+    pub keymap: &'a Keymap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MainItem {
+    Resume,
+    Palette,
+    Speed,
+    Quirks,
+    Volume,
+    Scale,
+    SaveSlot,
+    SaveState,
+    LoadState,
+    Keymap,
+    LoadRom,
+}
+
+const MAIN_ITEMS: [MainItem; 11] = [
+    MainItem::Resume,
+    MainItem::Palette,
+    MainItem::Speed,
+    MainItem::Quirks,
+    MainItem::Volume,
+    MainItem::Scale,
+    MainItem::SaveSlot,
+    MainItem::SaveState,
+    MainItem::LoadState,
+    MainItem::Keymap,
+    MainItem::LoadRom,
+];
+
+struct FileEntry {
+    name: String,
+    path: PathBuf,
+    is_directory: bool,
+}
+
+enum Page {
+    Main,
+    Keymap {
+        // Keypad key waiting for a key to be pressed
+        binding: Option<u8>,
+    },
+    Files {
+        directory: PathBuf,
+        entries: Vec<FileEntry>,
+    },
+}
+
+/// Settings menu drawn over the display.
+pub struct Menu {
+    page: Page,
+    selected: usize,
+    // Where the file picker starts
+    rom_directory: PathBuf,
+}
+
+impl Menu {
+    /// Opens on the main page, the file picker starts next to `rom_path`.
+    pub fn new(rom_path: &str) -> Self {
+        let rom_directory = Path::new(rom_path)
+            .parent()
+            .filter(|directory| !directory.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+        Menu {
+            page: Page::Main,
+            selected: 0,
+            rom_directory,
+        }
+    }
+
+    /// The menu waits for any key to bind to the keypad.
+    pub fn is_binding(&self) -> bool {
+        matches!(self.page, Page::Keymap { binding: Some(_) })
+    }
+
+    /// Binds the key called `key_name` to the keypad key being rebound.
+    pub fn bind_key(&mut self, key_name: &str) -> Option<MenuAction> {
+        let Page::Keymap { binding } = &mut self.page else {
+            return None;
+        };
+        binding.take().map(|chip8_key| MenuAction::BindKey {
+            key_name: key_name.to_string(),
+            chip8_key,
+        })
+    }
+
+    pub fn cancel_binding(&mut self) {
+        if let Page::Keymap { binding } = &mut self.page {
+            *binding = None;
+        }
+    }
+
+    pub fn handle_input(&mut self, input: MenuInput) -> Option<MenuAction> {
+        // A directory listing can come up empty
+        let item_count = self.item_count().max(1);
+        match input {
+            MenuInput::Up => {
+                self.selected = (self.selected + item_count - 1) % item_count;
+                None
+            }
+            MenuInput::Down => {
+                self.selected = (self.selected + 1) % item_count;
+                None
+            }
+            MenuInput::Back => match self.page {
+                Page::Main => Some(MenuAction::Close),
+                _ => {
+                    self.open_page(Page::Main);
+                    None
+                }
+            },
+            MenuInput::Left | MenuInput::Right | MenuInput::Select => match self.page {
+                Page::Main => self.handle_main_input(input),
+                Page::Keymap { .. } => {
+                    if input == MenuInput::Select {
+                        self.page = Page::Keymap {
+                            binding: Some(self.selected as u8),
+                        };
+                    }
+                    None
+                }
+                Page::Files { .. } => {
+                    if input == MenuInput::Select {
+                        self.select_file()
+                    } else {
+                        None
+                    }
+                }
+            },
+        }
+    }
+
+    fn handle_main_input(&mut self, input: MenuInput) -> Option<MenuAction> {
+        let forward = input != MenuInput::Left;
+        match (MAIN_ITEMS[self.selected], input) {
+            (MainItem::Resume, MenuInput::Select) => Some(MenuAction::Close),
+            (MainItem::Palette, _) => Some(MenuAction::Palette { forward }),
+            (MainItem::Speed, MenuInput::Left) => Some(MenuAction::Slower),
+            (MainItem::Speed, MenuInput::Right) => Some(MenuAction::Faster),
+            (MainItem::Speed, _) => Some(MenuAction::NextTiming),
+            (MainItem::Quirks, _) => Some(MenuAction::Quirks { forward }),
+            (MainItem::Volume, MenuInput::Select) => Some(MenuAction::ToggleMute),
+            (MainItem::Volume, _) => Some(MenuAction::Volume { louder: forward }),
+            (MainItem::Scale, _) => Some(MenuAction::Scale { larger: forward }),
+            (MainItem::SaveSlot, _) => Some(MenuAction::SaveSlot { forward }),
+            (MainItem::SaveState, MenuInput::Select) => Some(MenuAction::SaveState),
+            (MainItem::LoadState, MenuInput::Select) => Some(MenuAction::LoadState),
+            (MainItem::Keymap, MenuInput::Select) => {
+                self.open_page(Page::Keymap { binding: None });
+                None
+            }
+            (MainItem::LoadRom, MenuInput::Select) => {
+                let directory = self.rom_directory.clone();
+                self.open_directory(directory);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn select_file(&mut self) -> Option<MenuAction> {
+        let Page::Files { entries, .. } = &self.page else {
+            return None;
+        };
+        let entry = entries.get(self.selected)?;
+        if entry.is_directory {
+            let directory = entry.path.clone();
+            self.open_directory(directory);
+            None
+        } else {
+            self.rom_directory = entry.path.parent()?.to_path_buf();
+            Some(MenuAction::OpenRom(entry.path.clone()))
+        }
+    }
+
+    fn open_page(&mut self, page: Page) {
+        self.page = page;
+        self.selected = 0;
+    }
+
+    fn open_directory(&mut self, directory: PathBuf) {
+        let directory = directory.canonicalize().unwrap_or(directory);
+        let mut entries: Vec<FileEntry> = directory
+            .parent()
+            .map(|parent| FileEntry {
+                name: "../".to_string(),
+                path: parent.to_path_buf(),
+                is_directory: true,
+            })
+            .into_iter()
+            .collect();
+        match fs::read_dir(&directory) {
+            Ok(read_dir) => {
+                let mut listed: Vec<FileEntry> = read_dir
+                    .flatten()
+                    .map(|dir_entry| {
+                        let is_directory = dir_entry.path().is_dir();
+                        let mut name = dir_entry.file_name().to_string_lossy().into_owned();
+                        if is_directory {
+                            name.push('/');
+                        }
+                        FileEntry {
+                            name,
+                            path: dir_entry.path(),
+                            is_directory,
+                        }
+                    })
+                    .filter(|entry| !entry.name.starts_with('.'))
+                    .collect();
+                // Directories first, then files, each alphabetically
+                listed.sort_by_key(|entry| (!entry.is_directory, entry.name.to_lowercase()));
+                entries.extend(listed);
+            }
+            Err(error) => eprintln!("Couldn't list {}: {error}", directory.display()),
+        }
+        self.open_page(Page::Files { directory, entries });
+    }
+
+    fn item_count(&self) -> usize {
+        match &self.page {
+            Page::Main => MAIN_ITEMS.len(),
+            Page::Keymap { .. } => 16,
+            Page::Files { entries, .. } => entries.len(),
+        }
+    }
+
+    fn title(&self) -> String {
+        match &self.page {
+            Page::Main => "MENU - ARROWS CHANGE, ENTER SELECTS".to_string(),
+            Page::Keymap {
+                binding: Some(chip8_key),
+            } => format!("PRESS A KEY FOR KEYPAD {chip8_key:X}, ESC CANCELS"),
+            Page::Keymap { binding: None } => "KEYMAP - ENTER REBINDS, ESC GOES BACK".to_string(),
+            Page::Files { directory, .. } => {
+                // The end of a long path says more than its start
+                let directory: Vec<char> = directory.display().to_string().chars().collect();
+                directory[directory.len().saturating_sub(COLUMNS)..]
+                    .iter()
+                    .collect()
+            }
+        }
+    }
+
+    fn items(&self, status: &MenuStatus) -> Vec<String> {
+        match &self.page {
+            Page::Main => MAIN_ITEMS
+                .iter()
+                .map(|item| main_item_label(*item, status))
+                .collect(),
+            Page::Keymap { .. } => (0..16u8)
+                .map(|chip8_key| {
+                    let names: Vec<&str> = status
+                        .keymap
+                        .keys()
+                        .filter(|(_, key)| *key == chip8_key)
+                        .map(|(name, _)| name)
+                        .collect();
+                    format!("KEYPAD {chip8_key:X}   {}", names.join(", "))
+                })
+                .collect(),
+            Page::Files { entries, .. } => entries.iter().map(|entry| entry.name.clone()).collect(),
+        }
+    }
+
+    /// Draws the menu into an `OVERLAY_WIDTH` by `OVERLAY_HEIGHT` bitmap of
+    /// `OVERLAY_*` values.
+    pub fn render(&self, status: &MenuStatus) -> Vec<u8> {
+        let mut overlay = vec![OVERLAY_SHADE; OVERLAY_WIDTH * OVERLAY_HEIGHT];
+        draw_text(&mut overlay, MARGIN, &self.title(), OVERLAY_TEXT);
+
+        let items = self.items(status);
+        let first = (self.selected + 1).saturating_sub(VISIBLE_ITEMS);
+        for (row, item) in items.iter().enumerate().skip(first).take(VISIBLE_ITEMS) {
+            let y = FIRST_ITEM_Y + (row - first) * LINE_HEIGHT;
+            let color = if row == self.selected {
+                let bar = &mut overlay[(y - 1) * OVERLAY_WIDTH..(y + 6) * OVERLAY_WIDTH];
+                bar.fill(OVERLAY_HIGHLIGHT);
+                OVERLAY_HIGHLIGHTED_TEXT
+            } else {
+                OVERLAY_TEXT
+            };
+            draw_text(&mut overlay, y, item, color);
+        }
+        overlay
+    }
+}
+
+fn main_item_label(item: MainItem, status: &MenuStatus) -> String {
+    let volume = if status.muted {
+        "MUTED".to_string()
+    } else {
+        format!("{}%", (status.volume * 100.0).round())
+    };
+    let timing = match status.timing_model {
+        TimingModel::Vip => "VIP".to_string(),
+        TimingModel::InstructionsPerFrame(count) => format!("{count} PER FRAME"),
+        TimingModel::InstructionsPerSecond(count) => format!("{count} PER SECOND"),
+    };
+    match item {
+        MainItem::Resume => "RESUME".to_string(),
+        MainItem::Palette => format!("PALETTE      < {} >", status.palette),
+        MainItem::Speed => format!("SPEED        < {timing} >"),
+        MainItem::Quirks => format!("QUIRKS       < {} >", status.quirks),
+        MainItem::Volume => format!("VOLUME       < {volume} >"),
+        MainItem::Scale => format!("WINDOW SIZE  < {}X >", status.scale),
+        MainItem::SaveSlot => format!("SAVE SLOT    < {} >", status.save_slot),
+        MainItem::SaveState => "SAVE STATE".to_string(),
+        MainItem::LoadState => "LOAD STATE".to_string(),
+        MainItem::Keymap => "KEYMAP".to_string(),
+        MainItem::LoadRom => "LOAD ROM".to_string(),
+    }
+}
+
+/// Draws a line of text at row `y`, cut off at the right edge.
+fn draw_text(overlay: &mut [u8], y: usize, text: &str, color: u8) {
+    for (column, character) in text.chars().take(COLUMNS).enumerate() {
+        let x = MARGIN + column * CHARACTER_WIDTH;
+        for (row, bits) in glyph(character).iter().enumerate() {
+            for bit in 0..4 {
+                if bits & (0x80 >> bit) != 0 {
+                    overlay[(y + row) * OVERLAY_WIDTH + x + bit] = color;
+                }
+            }
+        }
+    }
+}
+
+fn glyph(character: char) -> [u8; 5] {
+    let character = character.to_ascii_uppercase();
+    if let Some(digit) = character.to_digit(16) {
+        let start = digit as usize * 5;
+        let mut glyph = [0; 5];
+        glyph.copy_from_slice(&FONT[start..start + 5]);
+        return glyph;
+    }
+    EXTRA_GLYPHS
+        .iter()
+        .find(|(extra, _)| *extra == character)
+        .or_else(|| EXTRA_GLYPHS.iter().find(|(extra, _)| *extra == '?'))
+        .map(|(_, glyph)| *glyph)
+        .unwrap()
+}
@@ -0,0 +1,188 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chip8::Chip8;
+use crate::file_error::{self, FileError};
+use crate::quirks::Quirks;
+use crate::timing::TimingModel;
+
+/// Frames between state hashes in a movie.
+const CHECKPOINT_INTERVAL: u64 = 60;
+
+/// Keypad input recorded frame by frame, with everything needed to play it
+/// back the same way.
+///
+/// Movies are TOML files:
+///
+/// ```toml
+/// rom_hash = "0df2789f661358d8f7370e6cf93490c5bcd44b01"
+/// seed = "1234"
+/// frames = 600
+/// inputs = [[12, 5, true], [20, 5, false]]
+/// checkpoints = [[60, "5f2c..."], [120, "9a41..."]]
+///
+/// [quirks]
+/// shift_uses_vy = true
+/// ...
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Movie {
+    /// SHA-1 of the rom the movie was recorded with.
+    pub rom_hash: String,
+    /// Kept as a string, TOML integers don't go past `i64::MAX`.
+    #[serde(with = "seed_string")]
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub timing_model: TimingModel,
+    /// Number of frames recorded.
+    pub frames: u64,
+    /// Frame, keypad key and whether it was pressed, in the order they happened.
+    pub inputs: Vec<(u64, u8, bool)>,
+    /// Frame and SHA-1 of the machine state right after it ran.
+    pub checkpoints: Vec<(u64, String)>,
+}
+
+impl Movie {
+    /// An empty movie starting from `chip8` right after the rom with
+    /// `rom_hash` was loaded.
+    pub fn new(rom_hash: String, seed: u64, chip8: &Chip8) -> Self {
+        Movie {
+            rom_hash,
+            seed,
+            quirks: chip8.quirks(),
+            timing_model: chip8.timing_model(),
+            frames: 0,
+            inputs: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, FileError> {
+        file_error::load(path, Self::parse)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let movie: Movie = toml::from_str(text).map_err(|error| error.message().to_string())?;
+        if let Some((frame, key, _)) = movie.inputs.iter().find(|(_, key, _)| *key > 0xF) {
+            return Err(format!(
+                "input on frame {frame} presses key {key:#X}, keypad keys go up to 0xF"
+            ));
+        }
+        Ok(movie)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), FileError> {
+        let text =
+            toml::to_string(self).map_err(|error| FileError::new(path, error.to_string()))?;
+        fs::write(path, text).map_err(|error| FileError::new(path, error.to_string()))
+    }
+}
+
+/// Collects input into a movie while the emulator runs.
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    pub fn new(movie: Movie) -> Self {
+        MovieRecorder { movie }
+    }
+
+    /// Records a key change that happens before the next frame runs.
+    pub fn record_key(&mut self, key: u8, pressed: bool) {
+        self.movie.inputs.push((self.movie.frames, key, pressed));
+    }
+
+    /// Call after every frame `chip8` runs.
+    pub fn end_frame(&mut self, chip8: &Chip8) {
+        self.movie.frames += 1;
+        if self.movie.frames % CHECKPOINT_INTERVAL == 0 {
+            self.movie
+                .checkpoints
+                .push((self.movie.frames, state_hash(chip8)));
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+/// Feeds a movie's input back and checks the machine stays in sync with it.
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: u64,
+    next_input: usize,
+    next_checkpoint: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer {
+            movie,
+            frame: 0,
+            next_input: 0,
+            next_checkpoint: 0,
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Applies the input recorded before the next frame.
+    pub fn start_frame(&mut self, chip8: &mut Chip8) {
+        while let Some((frame, key, pressed)) = self.movie.inputs.get(self.next_input) {
+            if *frame > self.frame {
+                break;
+            }
+            chip8.change_key_state(*key, *pressed);
+            self.next_input += 1;
+        }
+    }
+
+    /// Call after every frame `chip8` runs, fails with the frame number if the
+    /// machine state doesn't match the recording.
+    pub fn end_frame(&mut self, chip8: &Chip8) -> Result<(), u64> {
+        self.frame += 1;
+        match self.movie.checkpoints.get(self.next_checkpoint) {
+            Some((frame, hash)) if *frame == self.frame => {
+                self.next_checkpoint += 1;
+                if *hash != state_hash(chip8) {
+                    return Err(self.frame);
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames
+    }
+}
+
+/// SHA-1 of everything in a save state.
+pub fn state_hash(chip8: &Chip8) -> String {
+    sha1_smol::Sha1::from(chip8.save_state())
+        .digest()
+        .to_string()
+}
+
+mod seed_string {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(seed: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(seed)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
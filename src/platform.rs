@@ -1,109 +1,854 @@
-use std::{fs::File, io::Read, time::Instant};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::{fs, time::Instant};
 
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::messagebox::{show_simple_message_box, MessageBoxFlag};
 use sdl2::EventPump;
 
-use crate::chip8::Chip8;
-use crate::config::Config;
+use crate::audio_device::AudioDevice;
+use crate::chip8::{Chip8, Chip8Error, FRAME_DURATION};
+use clap::ValueEnum;
+
+use crate::config::{QuirksPreset, Settings};
+use crate::controller_device::ControllerDevice;
+use crate::debugger::{self, Debugger};
 use crate::display_device::DisplayDevice;
+use crate::keymap::Keymap;
+use crate::menu::{Menu, MenuAction, MenuInput, MenuStatus, PALETTES};
+use crate::movie::{Movie, MoviePlayer, MovieRecorder};
+use crate::quirks::Quirks;
+use crate::rewind::Rewinder;
+use crate::rom_database::rom_hash;
+use crate::rom_watcher::RomWatcher;
+use crate::timing::TimingModel;
 
 pub struct Platform {
     chip8: Chip8,
     display_device: DisplayDevice,
+    audio_device: AudioDevice,
     event_pump: EventPump,
-    previous_tick: Instant,
+    rom_path: String,
+    save_slot: u8,
+    debugger: Option<Debugger>,
+    debugger_commands: Option<Receiver<String>>,
+    keymap: Keymap,
+    keycodes: HashMap<Keycode, u8>,
+    // Keys bound in the menu, they stay bound when another rom is loaded
+    bound_keys: Vec<(String, u8)>,
+    controller_device: ControllerDevice,
+    settings: Settings,
+    seed: u64,
+    rom_hash: String,
+    movie_recorder: Option<MovieRecorder>,
+    movie_player: Option<MoviePlayer>,
+    rewinder: Option<Rewinder>,
+    rewinding: bool,
+    rom_data: Vec<u8>,
+    paused: bool,
+    advance_frame: bool,
+    rom_watcher: Option<RomWatcher>,
+    menu: Option<Menu>,
+    // 0 is the rom's own palette, the rest index `PALETTES` from 1
+    palette_choice: usize,
+    rom_palette: [u32; 4],
+    volume: f32,
+    muted: bool,
+    scale: u32,
 }
 
+const SAVE_SLOTS: u8 = 10;
+const VOLUME_STEP: f32 = 0.05;
+const MAX_SCALE: u32 = 63;
+
 impl Platform {
-    pub fn new(config: Config) -> Self {
+    pub fn new(settings: Settings) -> Result<Self, Chip8Error> {
+        let config = &settings.config;
+        let rom_path = config
+            .rom_path
+            .clone()
+            .expect("Rom path is required without a subcommand");
         let sdl_context = sdl2::init().unwrap();
+        let seed = match &settings.replay {
+            Some(movie) => movie.seed,
+            None => config.seed.unwrap_or_else(rand::random),
+        };
+        println!("Using seed {seed}");
+
+        let rewinder = (config.rewind_seconds > 0).then(|| {
+            Rewinder::new(
+                config.rewind_seconds * 60,
+                config.rewind_interval,
+                config.rewind_memory << 20,
+            )
+        });
+
+        let rom_watcher = config.watch.then(|| RomWatcher::new(&rom_path));
+        let (volume, muted, scale) = (config.volume, config.mute, config.scale);
 
         let mut platform = Self {
-            chip8: Chip8::new(),
+            chip8: Chip8::new(Quirks::default(), seed),
             display_device: DisplayDevice::new(
                 &sdl_context,
-                config.background_color,
-                config.sprite_color,
+                config.scale,
+                config.integer_scale,
+                config.vsync,
+            ),
+            audio_device: AudioDevice::new(
+                &sdl_context,
+                config.audio_frequency,
+                config.volume,
+                config.waveform,
+                config.mute,
             ),
             event_pump: sdl_context.event_pump().unwrap(),
-            previous_tick: Instant::now(),
+            rom_path: rom_path.clone(),
+            save_slot: 0,
+            debugger: None,
+            debugger_commands: None,
+            keymap: Keymap::default(),
+            keycodes: HashMap::new(),
+            bound_keys: Vec::new(),
+            controller_device: ControllerDevice::new(&sdl_context, &Keymap::default()),
+            settings,
+            seed,
+            rom_hash: String::new(),
+            movie_recorder: None,
+            movie_player: None,
+            rewinder,
+            rewinding: false,
+            rom_data: Vec::new(),
+            paused: false,
+            advance_frame: false,
+            rom_watcher,
+            menu: None,
+            palette_choice: 0,
+            rom_palette: [0; 4],
+            volume,
+            muted,
+            scale,
         };
-        platform.load_rom(&config.rom_path);
-        platform
+        platform.display_device.set_filter(
+            platform.settings.config.display_filter,
+            platform.settings.config.phosphor_decay,
+        );
+        if platform.settings.config.debug {
+            Debugger::print_help();
+            platform.debugger = Some(Debugger::new());
+            platform.debugger_commands = Some(debugger::spawn_console());
+        }
+        platform.load_rom(&rom_path)?;
+        platform.start_movie();
+        Ok(platform)
+    }
+
+    /// Starts replaying or recording a movie if the settings ask for one.
+    fn start_movie(&mut self) {
+        if let Some(movie) = self.settings.replay.take() {
+            if movie.rom_hash != self.rom_hash {
+                eprintln!("The movie was recorded with a different rom and will likely desync");
+            }
+            self.chip8.set_quirks(movie.quirks);
+            self.set_timing_model(movie.timing_model);
+            println!("Replaying {} frames of input", movie.frames);
+            self.movie_player = Some(MoviePlayer::new(movie));
+        } else if let Some(movie_path) = &self.settings.config.record {
+            println!("Recording input to {movie_path}");
+            let movie = Movie::new(self.rom_hash.clone(), self.seed, &self.chip8);
+            self.movie_recorder = Some(MovieRecorder::new(movie));
+        }
+    }
+
+    fn save_movie(&self) {
+        if let (Some(recorder), Some(movie_path)) =
+            (&self.movie_recorder, &self.settings.config.record)
+        {
+            match recorder.movie().save(Path::new(movie_path)) {
+                Ok(()) => println!("Saved {} frames of input", recorder.movie().frames),
+                Err(error) => eprintln!("Couldn't save movie {error}"),
+            }
+        }
     }
 
-    pub fn load_rom(&mut self, rom_path: &String) {
-        let mut rom_file = File::open(rom_path).expect("Couldn't find rom file");
-        let mut rom_data = Vec::new();
-        rom_file.read_to_end(&mut rom_data).unwrap();
+    /// Recorded and replayed input only stays in sync if nothing but the
+    /// keypad changes the machine.
+    fn is_movie_running(&self, action: &str) -> bool {
+        let running = self.movie_recorder.is_some() || self.movie_player.is_some();
+        if running {
+            eprintln!("Can't {action} while a movie is recording or playing");
+        }
+        running
+    }
 
-        self.chip8.load_rom(&rom_data);
+    fn change_key_state(&mut self, chip8_key: u8, pressed: bool) {
+        // A movie being replayed is the only input
+        if self.movie_player.is_some() {
+            return;
+        }
+        if let Some(recorder) = &mut self.movie_recorder {
+            recorder.record_key(chip8_key, pressed);
+        }
+        self.chip8.change_key_state(chip8_key, pressed);
     }
 
-    pub fn start(&mut self) {
-        self.previous_tick = Instant::now();
+    fn end_movie_frame(&mut self) {
+        if let Some(recorder) = &mut self.movie_recorder {
+            recorder.end_frame(&self.chip8);
+        }
+        if let Some(player) = &mut self.movie_player {
+            if let Err(frame) = player.end_frame(&self.chip8) {
+                eprintln!("The movie desynced at frame {frame}");
+            }
+            if player.is_finished() {
+                println!("Movie finished, the keypad is live again");
+                self.movie_player = None;
+            }
+        }
+    }
 
-        'mainloop: loop {
-            let delta_time = self.previous_tick.elapsed();
-            self.previous_tick = Instant::now();
+    pub fn load_rom(&mut self, rom_path: &str) -> Result<(), Chip8Error> {
+        let rom_data = Self::read_rom(rom_path)?;
+        self.apply_rom(rom_path, rom_data)
+    }
+
+    fn read_rom(rom_path: &str) -> Result<Vec<u8>, Chip8Error> {
+        let error = |reason: String| Chip8Error::UnreadableRom {
+            path: rom_path.to_string(),
+            reason,
+        };
+        match fs::read(rom_path) {
+            // Probably caught in the middle of being written
+            Ok(rom_data) if rom_data.is_empty() => Err(error("the file is empty".to_string())),
+            Ok(rom_data) => Ok(rom_data),
+            Err(io_error) => Err(error(io_error.to_string())),
+        }
+    }
+
+    /// Loads `rom_data` into the machine and applies its settings, nothing
+    /// changes if it doesn't fit.
+    fn apply_rom(&mut self, rom_path: &str, rom_data: Vec<u8>) -> Result<(), Chip8Error> {
+        self.chip8.load_rom(&rom_data)?;
+
+        let rom_config = self.settings.for_rom(rom_path, &rom_data);
+        if let Some(name) = &rom_config.name {
+            println!("Recognised {name}");
+        }
+        self.rom_hash = rom_hash(&rom_data);
+        self.rom_data = rom_data;
+        if let Some(rewinder) = &mut self.rewinder {
+            rewinder.clear();
+        }
+        self.chip8.set_quirks(rom_config.quirks);
+        self.set_timing_model(rom_config.timing_model);
+        self.rom_palette = rom_config.palette;
+        self.apply_palette();
+        self.keymap = rom_config.keymap;
+        for (key_name, chip8_key) in &self.bound_keys {
+            self.keymap.bind_key(key_name, *chip8_key);
+        }
+        self.keycodes = Self::keycode_map(&self.keymap);
+        self.controller_device.set_keymap(&self.keymap);
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.advance_frame = false;
+        println!("{}", if paused { "Paused" } else { "Resumed" });
+    }
+
+    /// Runs a single frame while paused.
+    pub fn advance_frame(&mut self) {
+        if self.paused {
+            self.advance_frame = true;
+        }
+    }
+
+    /// Starts the loaded rom over from a fresh machine.
+    pub fn reset(&mut self) -> Result<(), Chip8Error> {
+        if self.is_movie_running("reset") {
+            return Ok(());
+        }
+        self.chip8.reset();
+        self.chip8.load_rom(&self.rom_data)?;
+        self.after_reset(true);
+        println!("Reset");
+        Ok(())
+    }
+
+    /// Starts the rom over but keeps memory as the rom left it.
+    pub fn soft_reset(&mut self) {
+        if self.is_movie_running("reset") {
+            return;
+        }
+        self.chip8.soft_reset();
+        self.after_reset(true);
+        println!("Soft reset");
+    }
+
+    /// Reads the rom file again and starts it on a fresh machine.
+    pub fn reload_rom(&mut self, keep_breakpoints: bool) {
+        if self.is_movie_running("reload the rom") {
+            return;
+        }
+        if self.load_rom_file(&self.rom_path.clone(), keep_breakpoints) {
+            println!("Reloaded {}", self.rom_path);
+        }
+    }
+
+    /// Switches to another rom file, save states and `--watch` follow it.
+    pub fn open_rom(&mut self, rom_path: &str) -> bool {
+        if self.is_movie_running("load another rom") || !self.load_rom_file(rom_path, false) {
+            return false;
+        }
+        self.rom_path = rom_path.to_string();
+        if self.rom_watcher.is_some() {
+            self.rom_watcher = Some(RomWatcher::new(rom_path));
+        }
+        println!("Loaded {rom_path}");
+        true
+    }
+
+    /// Starts the rom file at `rom_path` on a fresh machine. Problems with the
+    /// file are reported and the current rom keeps running.
+    fn load_rom_file(&mut self, rom_path: &str, keep_breakpoints: bool) -> bool {
+        let previous = self.chip8.clone();
+        let result = Self::read_rom(rom_path).and_then(|rom_data| {
+            self.chip8.reset();
+            self.apply_rom(rom_path, rom_data)
+        });
+        if let Err(error) = result {
+            eprintln!("Keeping the current rom, {error}");
+            self.chip8 = previous;
+            return false;
+        }
+        self.after_reset(keep_breakpoints);
+        true
+    }
+
+    fn after_reset(&mut self, keep_breakpoints: bool) {
+        if let Some(debugger) = &mut self.debugger {
+            debugger.reset(keep_breakpoints);
+        }
+        if let Some(rewinder) = &mut self.rewinder {
+            rewinder.clear();
+        }
+        self.audio_device.set_playing(false);
+        self.draw();
+    }
+
+    fn save_state_path(&self) -> String {
+        format!("{}.state{}", self.rom_path, self.save_slot)
+    }
+
+    pub fn save_state(&self) {
+        let path = self.save_state_path();
+        match fs::write(&path, self.chip8.save_state()) {
+            Ok(()) => println!("Saved state to slot {}", self.save_slot),
+            Err(error) => eprintln!("Couldn't write save state {path}: {error}"),
+        }
+    }
+
+    pub fn load_state(&mut self) {
+        let path = self.save_state_path();
+        let result = fs::read(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|data| {
+                restore_with_live_keys(&mut self.chip8, |chip8| chip8.load_state(&data))
+                    .map_err(|error| error.to_string())
+            });
+        match result {
+            Ok(()) => {
+                if let Some(rewinder) = &mut self.rewinder {
+                    rewinder.clear();
+                }
+                self.draw();
+                println!("Loaded state from slot {}", self.save_slot);
+            }
+            Err(error) => eprintln!("Couldn't load save state {path}: {error}"),
+        }
+    }
+
+    pub fn select_save_slot(&mut self, slot: u8) {
+        self.save_slot = slot % SAVE_SLOTS;
+        println!("Selected save slot {}", self.save_slot);
+    }
+
+    pub fn set_timing_model(&mut self, timing_model: TimingModel) {
+        self.chip8.set_timing_model(timing_model);
+        println!("Using {timing_model}");
+    }
+
+    fn apply_palette(&mut self) {
+        let palette = match self.palette_choice {
+            0 => self.rom_palette,
+            choice => PALETTES[choice - 1].1,
+        };
+        self.display_device.set_palette(palette);
+    }
+
+    fn palette_name(&self) -> &'static str {
+        match self.palette_choice {
+            0 => "ROM",
+            choice => PALETTES[choice - 1].0,
+        }
+    }
+
+    /// The preset the quirks match, whatever FX0A waits for.
+    fn quirks_preset(&self) -> Option<QuirksPreset> {
+        let quirks = self.chip8.quirks();
+        QuirksPreset::value_variants()
+            .iter()
+            .copied()
+            .find(|preset| {
+                Quirks {
+                    key_wait_on_press: quirks.key_wait_on_press,
+                    ..preset.quirks()
+                } == quirks
+            })
+    }
+
+    fn select_quirks(&mut self, forward: bool) {
+        let presets = QuirksPreset::value_variants();
+        let index = match self.quirks_preset() {
+            Some(preset) => {
+                let index = presets.iter().position(|other| *other == preset).unwrap();
+                let step = if forward { 1 } else { presets.len() - 1 };
+                (index + step) % presets.len()
+            }
+            // Custom quirks from the rom database
+            None => 0,
+        };
+        let quirks = self.chip8.quirks();
+        self.chip8.set_quirks(Quirks {
+            key_wait_on_press: quirks.key_wait_on_press,
+            ..presets[index].quirks()
+        });
+        println!("Using {} quirks", preset_name(presets[index]));
+    }
+
+    fn open_menu(&mut self) {
+        // Keys held now would otherwise stay pressed until the menu closes
+        let keys = *self.chip8.keys();
+        for (chip8_key, pressed) in keys.into_iter().enumerate() {
+            if pressed {
+                self.change_key_state(chip8_key as u8, false);
+            }
+        }
+        self.rewinding = false;
+        self.audio_device.set_playing(false);
+        self.menu = Some(Menu::new(&self.rom_path));
+    }
+
+    fn close_menu(&mut self) {
+        self.menu = None;
+        self.display_device.set_overlay(None);
+    }
+
+    /// Keyboard input goes to the menu while it's open, controllers are
+    /// tracked but don't reach the keypad.
+    fn handle_menu_event(&mut self, event: &Event) {
+        let Event::KeyDown {
+            keycode: Some(keycode),
+            ..
+        } = *event
+        else {
+            self.controller_device.handle_event(event);
+            return;
+        };
+        let Some(menu) = &mut self.menu else {
+            return;
+        };
+        let action = if menu.is_binding() {
+            match keycode {
+                Keycode::Escape => {
+                    menu.cancel_binding();
+                    None
+                }
+                keycode if is_hotkey(keycode) => {
+                    eprintln!("{} is a hotkey and can't be bound", keycode.name());
+                    None
+                }
+                keycode => menu.bind_key(&keycode.name()),
+            }
+        } else {
+            let input = match keycode {
+                Keycode::Up => Some(MenuInput::Up),
+                Keycode::Down => Some(MenuInput::Down),
+                Keycode::Left => Some(MenuInput::Left),
+                Keycode::Right => Some(MenuInput::Right),
+                Keycode::Return | Keycode::KpEnter | Keycode::Space => Some(MenuInput::Select),
+                Keycode::Escape | Keycode::Backspace => Some(MenuInput::Back),
+                Keycode::Tab => {
+                    self.close_menu();
+                    return;
+                }
+                _ => None,
+            };
+            input.and_then(|input| menu.handle_input(input))
+        };
+        if let Some(action) = action {
+            self.handle_menu_action(action);
+        }
+    }
+
+    fn handle_menu_action(&mut self, action: MenuAction) {
+        match action {
+            MenuAction::Close => self.close_menu(),
+            MenuAction::Palette { forward } => {
+                let choices = PALETTES.len() + 1;
+                let step = if forward { 1 } else { choices - 1 };
+                self.palette_choice = (self.palette_choice + step) % choices;
+                self.apply_palette();
+            }
+            MenuAction::Slower => {
+                if !self.is_movie_running("change the speed") {
+                    self.set_timing_model(self.chip8.timing_model().slower());
+                }
+            }
+            MenuAction::Faster => {
+                if !self.is_movie_running("change the speed") {
+                    self.set_timing_model(self.chip8.timing_model().faster());
+                }
+            }
+            MenuAction::NextTiming => {
+                if !self.is_movie_running("change the timing") {
+                    self.set_timing_model(self.chip8.timing_model().next());
+                }
+            }
+            MenuAction::Quirks { forward } => {
+                if !self.is_movie_running("change the quirks") {
+                    self.select_quirks(forward);
+                }
+            }
+            MenuAction::Volume { louder } => {
+                let step = if louder { VOLUME_STEP } else { -VOLUME_STEP };
+                self.volume = (self.volume + step).clamp(0.0, 1.0);
+                self.audio_device.set_volume(self.volume);
+            }
+            MenuAction::ToggleMute => {
+                self.muted = !self.muted;
+                self.audio_device.set_muted(self.muted);
+            }
+            MenuAction::Scale { larger } => {
+                self.scale = if larger {
+                    (self.scale + 1).min(MAX_SCALE)
+                } else {
+                    (self.scale - 1).max(1)
+                };
+                self.display_device.set_scale(self.scale);
+            }
+            MenuAction::SaveSlot { forward } => {
+                let step = if forward { 1 } else { SAVE_SLOTS - 1 };
+                self.select_save_slot(self.save_slot + step);
+            }
+            MenuAction::SaveState => self.save_state(),
+            MenuAction::LoadState => {
+                if !self.is_movie_running("load a state") {
+                    self.load_state();
+                }
+            }
+            MenuAction::BindKey {
+                key_name,
+                chip8_key,
+            } => {
+                self.keymap.bind_key(&key_name, chip8_key);
+                self.keycodes = Self::keycode_map(&self.keymap);
+                println!("Bound {key_name} to keypad key {chip8_key:X}");
+                self.bound_keys.push((key_name, chip8_key));
+            }
+            MenuAction::OpenRom(rom_path) => {
+                if self.open_rom(&rom_path.display().to_string()) {
+                    self.close_menu();
+                }
+            }
+        }
+    }
+
+    /// Steps back one snapshot.
+    fn rewind(&mut self) -> Result<(), Chip8Error> {
+        let Some(rewinder) = &mut self.rewinder else {
+            return Ok(());
+        };
+        if !restore_with_live_keys(&mut self.chip8, |chip8| rewinder.step_back(chip8))? {
+            println!("Reached the start of the rewind history");
+            self.rewinding = false;
+        }
+        Ok(())
+    }
 
-            let tick_result = self.chip8.tick(delta_time);
+    fn draw(&mut self) {
+        if let Some(menu) = &self.menu {
+            let quirks = self
+                .quirks_preset()
+                .map_or("CUSTOM".to_string(), preset_name);
+            let overlay = menu.render(&MenuStatus {
+                palette: self.palette_name(),
+                timing_model: self.chip8.timing_model(),
+                quirks: &quirks,
+                volume: self.volume,
+                muted: self.muted,
+                scale: self.scale,
+                save_slot: self.save_slot,
+                keymap: &self.keymap,
+            });
+            self.display_device.set_overlay(Some(&overlay));
+        }
+        self.display_device.draw(
+            &self.chip8.vram,
+            self.chip8.display_width(),
+            self.chip8.display_height(),
+        );
+    }
 
-            if tick_result.vram_changed {
-                self.display_device.draw(&self.chip8.vram);
+    pub fn show_fault(error: &Chip8Error) {
+        let message = format!("The emulator stopped: {error}");
+        eprintln!("{message}");
+        let _ = show_simple_message_box(MessageBoxFlag::ERROR, "Chip-8 fault", &message, None);
+    }
+
+    pub fn start(&mut self) -> Result<(), Chip8Error> {
+        let result = self.main_loop();
+        // Keep what was recorded up to a fault too
+        self.save_movie();
+        result
+    }
+
+    fn main_loop(&mut self) -> Result<(), Chip8Error> {
+        let mut next_frame = Instant::now();
+
+        'mainloop: loop {
+            if let (Some(debugger), Some(commands)) = (&mut self.debugger, &self.debugger_commands)
+            {
+                for command in commands.try_iter() {
+                    debugger.execute_command(&command, &self.chip8);
+                }
+            }
+            if self.rom_watcher.as_mut().is_some_and(RomWatcher::poll) {
+                self.reload_rom(self.settings.config.keep_breakpoints);
             }
 
-            for evt in self.event_pump.poll_iter() {
+            let events: Vec<Event> = self.event_pump.poll_iter().collect();
+            for evt in events {
                 match evt {
                     Event::Quit { .. } => {
                         break 'mainloop;
                     }
+                    event if self.menu.is_some() => self.handle_menu_event(&event),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Tab),
+                        ..
+                    } => self.open_menu(),
                     Event::KeyDown {
                         keycode: Some(Keycode::Escape),
                         ..
                     } => {
                         break 'mainloop;
                     }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F11),
+                        ..
+                    } => self.display_device.toggle_fullscreen(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F2),
+                        ..
+                    } => {
+                        if !self.is_movie_running("change the timing") {
+                            self.set_timing_model(self.chip8.timing_model().next());
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Minus),
+                        ..
+                    } => {
+                        if !self.is_movie_running("change the speed") {
+                            self.set_timing_model(self.chip8.timing_model().slower());
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Equals),
+                        ..
+                    } => {
+                        if !self.is_movie_running("change the speed") {
+                            self.set_timing_model(self.chip8.timing_model().faster());
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Backspace),
+                        repeat: false,
+                        ..
+                    } => {
+                        if self.rewinder.is_some() && !self.is_movie_running("rewind") {
+                            self.rewinding = true;
+                        }
+                    }
+                    Event::KeyUp {
+                        keycode: Some(Keycode::Backspace),
+                        ..
+                    } => self.rewinding = false,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F1),
+                        ..
+                    } => self.set_paused(!self.paused),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F10),
+                        ..
+                    } => self.advance_frame(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F3),
+                        keymod,
+                        ..
+                    } => {
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            self.soft_reset();
+                        } else {
+                            self.reset()?;
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F4),
+                        ..
+                    } => self.reload_rom(true),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F5),
+                        ..
+                    } => self.save_state(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F9),
+                        ..
+                    } => {
+                        if !self.is_movie_running("load a state") {
+                            self.load_state();
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F6),
+                        ..
+                    } => self.select_save_slot(self.save_slot + SAVE_SLOTS - 1),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F7),
+                        ..
+                    } => self.select_save_slot(self.save_slot + 1),
                     Event::KeyDown {
                         keycode: Some(key), ..
                     } => {
-                        if let Some(chip8_key) = Self::get_chip8_key(key) {
-                            self.chip8.change_key_state(chip8_key, true);
+                        if let Some(chip8_key) = self.keycodes.get(&key) {
+                            self.change_key_state(*chip8_key, true);
                         }
                     }
                     Event::KeyUp {
                         keycode: Some(key), ..
                     } => {
-                        if let Some(chip8_key) = Self::get_chip8_key(key) {
-                            self.chip8.change_key_state(chip8_key, false);
+                        if let Some(chip8_key) = self.keycodes.get(&key) {
+                            self.change_key_state(*chip8_key, false);
+                        }
+                    }
+                    event => {
+                        for (chip8_key, pressed) in self.controller_device.handle_event(&event) {
+                            self.change_key_state(chip8_key, pressed);
                         }
                     }
-                    _ => (),
                 }
             }
+
+            let paused = self.paused
+                || self.menu.is_some()
+                || self.debugger.as_ref().is_some_and(Debugger::is_paused);
+            self.display_device
+                .set_title(if paused { "Chip-8 (paused)" } else { "Chip-8" });
+            if self.rewinding {
+                self.audio_device.set_playing(false);
+                self.rewind()?;
+            } else if paused && !std::mem::take(&mut self.advance_frame) {
+                self.audio_device.set_playing(false);
+            } else {
+                if let Some(player) = &mut self.movie_player {
+                    player.start_frame(&mut self.chip8);
+                }
+                match &mut self.debugger {
+                    Some(debugger) => self.chip8.run_frame_with_hooks(debugger)?,
+                    None => self.chip8.run_frame()?,
+                };
+                self.end_movie_frame();
+                if let Some(rewinder) = &mut self.rewinder {
+                    rewinder.end_frame(&self.chip8);
+                }
+                self.audio_device
+                    .set_pattern(self.chip8.audio_pattern(), self.chip8.audio_pattern_rate());
+                self.audio_device.set_playing(self.chip8.sound_timer() > 0);
+
+                if self.chip8.has_exited() {
+                    break 'mainloop;
+                }
+            }
+            self.draw();
+
+            next_frame += FRAME_DURATION;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else if now - next_frame > FRAME_DURATION {
+                // Don't try to catch up after a stall, e.g. while the window was dragged
+                next_frame = now;
+            }
         }
+        Ok(())
     }
 
-    fn get_chip8_key(key: Keycode) -> Option<u8> {
-        match key {
-            Keycode::Num1 => Some(0x1),
-            Keycode::Num2 => Some(0x2),
-            Keycode::Num3 => Some(0x3),
-            Keycode::Num4 => Some(0xC),
-            Keycode::Q => Some(0x4),
-            Keycode::W => Some(0x5),
-            Keycode::E => Some(0x6),
-            Keycode::R => Some(0xD),
-            Keycode::A => Some(0x7),
-            Keycode::S => Some(0x8),
-            Keycode::D => Some(0x9),
-            Keycode::F => Some(0xE),
-            Keycode::Z => Some(0xA),
-            Keycode::X => Some(0x0),
-            Keycode::C => Some(0xB),
-            Keycode::V => Some(0xF),
-            _ => None,
+    fn keycode_map(keymap: &Keymap) -> HashMap<Keycode, u8> {
+        let mut keycodes = HashMap::new();
+        for (name, chip8_key) in keymap.keys() {
+            match Keycode::from_name(name) {
+                Some(keycode) => {
+                    keycodes.insert(keycode, chip8_key);
+                }
+                None => eprintln!("Ignoring unknown key name {name:?} in keymap"),
+            }
         }
+        keycodes
     }
 }
+
+/// Runs `restore` to bring back an earlier machine state, the keypad keeps the
+/// keys held right now instead of the ones held back then.
+fn restore_with_live_keys<T>(chip8: &mut Chip8, restore: impl FnOnce(&mut Chip8) -> T) -> T {
+    let keys = *chip8.keys();
+    let result = restore(chip8);
+    chip8.set_keys(keys);
+    result
+}
+
+/// Keys the emulator handles itself before the keymap sees them.
+fn is_hotkey(keycode: Keycode) -> bool {
+    matches!(
+        keycode,
+        Keycode::Escape
+            | Keycode::Tab
+            | Keycode::Backspace
+            | Keycode::Minus
+            | Keycode::Equals
+            | Keycode::F1
+            | Keycode::F2
+            | Keycode::F3
+            | Keycode::F4
+            | Keycode::F5
+            | Keycode::F6
+            | Keycode::F7
+            | Keycode::F9
+            | Keycode::F10
+            | Keycode::F11
+    )
+}
+
+fn preset_name(preset: QuirksPreset) -> String {
+    preset
+        .to_possible_value()
+        .map_or(String::new(), |value| value.get_name().to_uppercase())
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+/// Behaviours that differ between CHIP-8 interpreters.
+///
+/// See [Timendus' quirks test](https://github.com/Timendus/chip8-test-suite#quirks-test)
+/// for how each flag shows up in practice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift Vy into Vx instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    /// FX55/FX65 leave I pointing past the last register stored or loaded.
+    pub memory_increments_i: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to zero.
+    pub logic_resets_vf: bool,
+    /// BNNN jumps to XNN + VX instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// DXYN wraps sprites around the screen edges instead of clipping them.
+    pub sprites_wrap: bool,
+    /// DXYN waits for the next vertical blank before continuing.
+    pub display_wait: bool,
+    /// FX0A finishes as soon as a key is pressed instead of when it's released.
+    pub key_wait_on_press: bool,
+}
+
+impl Quirks {
+    /// Original COSMAC VIP interpreter.
+    pub const CHIP8: Quirks = Quirks {
+        shift_uses_vy: true,
+        memory_increments_i: true,
+        logic_resets_vf: true,
+        jump_uses_vx: false,
+        sprites_wrap: false,
+        display_wait: true,
+        key_wait_on_press: false,
+    };
+
+    /// SUPER-CHIP 1.1 on the HP48.
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        memory_increments_i: false,
+        logic_resets_vf: false,
+        jump_uses_vx: true,
+        sprites_wrap: false,
+        display_wait: false,
+        key_wait_on_press: false,
+    };
+
+    /// XO-CHIP as implemented by Octo.
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        memory_increments_i: true,
+        logic_resets_vf: false,
+        jump_uses_vx: false,
+        sprites_wrap: true,
+        display_wait: false,
+        key_wait_on_press: false,
+    };
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::CHIP8
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::VecDeque;
+
+use crate::chip8::{Chip8, Chip8Error};
+
+// Snapshot encodings, see `Rewinder::history`
+const XOR_DELTA: u8 = 0;
+const FULL: u8 = 1;
+
+/// Recent machine states to step back through.
+///
+/// Only the newest state is kept whole. Every older one is stored as the
+/// difference to the state after it, which is mostly zeros from one frame to
+/// the next and shrinks to a few bytes with run-length encoding.
+pub struct Rewinder {
+    interval: u32,
+    max_snapshots: usize,
+    memory_budget: usize,
+    frames_since_snapshot: u32,
+    latest: Option<Vec<u8>>,
+    // Oldest first, each one turns the state after it back into itself
+    history: VecDeque<Vec<u8>>,
+    history_bytes: usize,
+}
+
+impl Rewinder {
+    /// Keeps a snapshot every `interval` frames, going back at most `frames`
+    /// frames and using at most `memory_budget` bytes.
+    pub fn new(frames: u32, interval: u32, memory_budget: usize) -> Self {
+        let interval = interval.max(1);
+        Rewinder {
+            interval,
+            max_snapshots: (frames / interval) as usize,
+            memory_budget,
+            frames_since_snapshot: 0,
+            latest: None,
+            history: VecDeque::new(),
+            history_bytes: 0,
+        }
+    }
+
+    /// Call after every frame `chip8` runs.
+    pub fn end_frame(&mut self, chip8: &Chip8) {
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot < self.interval {
+            return;
+        }
+        self.frames_since_snapshot = 0;
+
+        let state = chip8.save_state();
+        if let Some(previous) = self.latest.replace(state) {
+            let snapshot = encode(&previous, self.latest.as_ref().unwrap());
+            self.history_bytes += snapshot.len();
+            self.history.push_back(snapshot);
+        }
+        while self.history.len() > self.max_snapshots || self.history_bytes > self.memory_budget {
+            match self.history.pop_front() {
+                Some(oldest) => self.history_bytes -= oldest.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Restores the snapshot before the newest one, returns `false` when
+    /// there's no history left.
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> Result<bool, Chip8Error> {
+        let (Some(latest), Some(snapshot)) = (&self.latest, self.history.pop_back()) else {
+            return Ok(false);
+        };
+        self.history_bytes -= snapshot.len();
+        let previous = decode(&snapshot, latest);
+        chip8.load_state(&previous)?;
+        self.latest = Some(previous);
+        self.frames_since_snapshot = 0;
+        Ok(true)
+    }
+
+    /// Forgets the history, e.g. after the machine jumped to another state.
+    pub fn clear(&mut self) {
+        self.frames_since_snapshot = 0;
+        self.latest = None;
+        self.history.clear();
+        self.history_bytes = 0;
+    }
+
+    /// Seconds of history at 60 frames per second.
+    pub fn seconds(&self) -> f32 {
+        (self.history.len() as u32 * self.interval) as f32 / 60.0
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.history_bytes + self.latest.as_ref().map_or(0, Vec::len)
+    }
+}
+
+/// `state` stored relative to `next`.
+fn encode(state: &[u8], next: &[u8]) -> Vec<u8> {
+    // Save states grow when the display switches resolution
+    if state.len() != next.len() {
+        let mut snapshot = vec![FULL];
+        run_length_encode(state, &mut snapshot);
+        return snapshot;
+    }
+    let difference: Vec<u8> = state.iter().zip(next).map(|(a, b)| a ^ b).collect();
+    let mut snapshot = vec![XOR_DELTA];
+    run_length_encode(&difference, &mut snapshot);
+    snapshot
+}
+
+fn decode(snapshot: &[u8], next: &[u8]) -> Vec<u8> {
+    let data = run_length_decode(&snapshot[1..]);
+    match snapshot[0] {
+        XOR_DELTA => data.iter().zip(next).map(|(a, b)| a ^ b).collect(),
+        _ => data,
+    }
+}
+
+/// Alternating zero run lengths and literal runs, each run length a LEB128
+/// varint.
+fn run_length_encode(data: &[u8], output: &mut Vec<u8>) {
+    let mut position = 0;
+    while position < data.len() {
+        let zeros = data[position..]
+            .iter()
+            .take_while(|byte| **byte == 0)
+            .count();
+        position += zeros;
+        let literals = data[position..]
+            .iter()
+            .take_while(|byte| **byte != 0)
+            .count();
+        write_varint(zeros, output);
+        write_varint(literals, output);
+        output.extend_from_slice(&data[position..position + literals]);
+        position += literals;
+    }
+}
+
+fn run_length_decode(mut input: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    while !input.is_empty() {
+        let zeros = read_varint(&mut input);
+        let literals = read_varint(&mut input);
+        data.resize(data.len() + zeros, 0);
+        data.extend_from_slice(&input[..literals]);
+        input = &input[literals..];
+    }
+    data
+}
+
+fn write_varint(mut value: usize, output: &mut Vec<u8>) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some((byte, rest)) = input.split_first() {
+        *input = rest;
+        value |= (*byte as usize & 0x7F) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::DISPLAY_WIDTH;
+    use crate::quirks::Quirks;
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        run_length_encode(data, &mut encoded);
+        run_length_decode(&encoded)
+    }
+
+    /// A rom that keeps changing a register, `I` and memory every frame.
+    fn counting_chip8() -> Chip8 {
+        let mut chip8 = Chip8::new(Quirks::default(), 0);
+        chip8
+            .load_rom(&[
+                0x70, 0x01, // ADD V0, 1
+                0xA3, 0x00, // LD I, 0x300
+                0xF0, 0x55, // LD [I], V0
+                0x12, 0x00, // JP 0x200
+            ])
+            .unwrap();
+        chip8
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 65536, usize::MAX] {
+            let mut encoded = Vec::new();
+            write_varint(value, &mut encoded);
+            let mut input = encoded.as_slice();
+            assert_eq!(read_varint(&mut input), value);
+            assert!(input.is_empty());
+        }
+        let mut encoded = Vec::new();
+        write_varint(300, &mut encoded);
+        assert_eq!(encoded, [0xAC, 0x02]);
+    }
+
+    #[test]
+    fn run_length_encoding_round_trips() {
+        let long_zero_run = [vec![1], vec![0; 1000], vec![2, 3]].concat();
+        let cases: [&[u8]; 7] = [
+            &[],
+            &[0],
+            &[0, 0, 0],
+            &[5],
+            &[5, 6, 0, 0, 7],
+            &[0, 0, 9, 9, 0],
+            &long_zero_run,
+        ];
+        for data in cases {
+            assert_eq!(round_trip(data), data);
+        }
+    }
+
+    #[test]
+    fn zero_runs_shrink() {
+        let mut encoded = Vec::new();
+        run_length_encode(&[0; 4096], &mut encoded);
+        // One zero run of 4096 followed by no literals
+        assert_eq!(encoded, [0x80, 0x20, 0x00]);
+    }
+
+    #[test]
+    fn snapshots_decode_against_the_next_state() {
+        let state = [1, 2, 3, 0, 0, 0, 4];
+        let next = [1, 2, 4, 0, 0, 0, 4];
+        let snapshot = encode(&state, &next);
+        assert_eq!(snapshot[0], XOR_DELTA);
+        assert_eq!(decode(&snapshot, &next), state);
+    }
+
+    #[test]
+    fn snapshots_survive_a_resolution_change() {
+        let state = [1, 2, 3];
+        let next = [1, 2, 3, 0, 0, 5];
+        let snapshot = encode(&state, &next);
+        assert_eq!(snapshot[0], FULL);
+        assert_eq!(decode(&snapshot, &next), state);
+        assert_eq!(decode(&encode(&next, &state), &state), next);
+    }
+
+    #[test]
+    fn steps_back_through_every_frame() {
+        let mut chip8 = counting_chip8();
+        let mut rewinder = Rewinder::new(600, 1, usize::MAX);
+        let mut states = Vec::new();
+        for _ in 0..100 {
+            chip8.run_frame().unwrap();
+            rewinder.end_frame(&chip8);
+            states.push(chip8.save_state());
+        }
+
+        // The newest snapshot is the current state, stepping back restores the ones before
+        for expected in states.iter().rev().skip(1) {
+            assert!(rewinder.step_back(&mut chip8).unwrap());
+            assert_eq!(&chip8.save_state(), expected);
+        }
+        assert!(!rewinder.step_back(&mut chip8).unwrap());
+    }
+
+    #[test]
+    fn steps_back_across_a_resolution_change() {
+        let mut chip8 = Chip8::new(Quirks::default(), 0);
+        chip8
+            .load_rom(&[
+                0x60, 0x05, // LD V0, 5
+                0xF0, 0x15, // LD DT, V0
+                0xF0, 0x07, // wait: LD V0, DT
+                0x30, 0x00, // SE V0, 0
+                0x12, 0x04, // JP wait
+                0x00, 0xFF, // HIGH
+                0x71, 0x01, // loop: ADD V1, 1
+                0x12, 0x0C, // JP loop
+            ])
+            .unwrap();
+        let mut rewinder = Rewinder::new(600, 1, usize::MAX);
+        let mut states = Vec::new();
+        for _ in 0..20 {
+            chip8.run_frame().unwrap();
+            rewinder.end_frame(&chip8);
+            states.push(chip8.save_state());
+        }
+        assert!(states[0].len() < states[19].len());
+
+        for expected in states.iter().rev().skip(1) {
+            assert!(rewinder.step_back(&mut chip8).unwrap());
+            assert_eq!(&chip8.save_state(), expected);
+        }
+        assert_eq!(chip8.display_width(), DISPLAY_WIDTH);
+    }
+
+    #[test]
+    fn history_is_capped_by_frames_and_memory() {
+        let mut chip8 = counting_chip8();
+        let mut rewinder = Rewinder::new(20, 2, usize::MAX);
+        for _ in 0..100 {
+            chip8.run_frame().unwrap();
+            rewinder.end_frame(&chip8);
+        }
+        assert_eq!(rewinder.seconds(), 20.0 / 60.0);
+
+        let budget = 200;
+        let mut rewinder = Rewinder::new(600, 1, budget);
+        for _ in 0..100 {
+            chip8.run_frame().unwrap();
+            rewinder.end_frame(&chip8);
+        }
+        assert!(rewinder.history_bytes <= budget);
+        assert!(rewinder.seconds() > 0.0);
+    }
+
+    #[test]
+    fn clear_forgets_the_history() {
+        let mut chip8 = counting_chip8();
+        let mut rewinder = Rewinder::new(600, 1, usize::MAX);
+        for _ in 0..10 {
+            chip8.run_frame().unwrap();
+            rewinder.end_frame(&chip8);
+        }
+        rewinder.clear();
+        assert_eq!(rewinder.memory_used(), 0);
+        assert!(!rewinder.step_back(&mut chip8).unwrap());
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::config::{QuirksPreset, Timing};
+use crate::file_error::{self, FileError};
+use crate::keymap::Keymap;
+
+/// Settings for a single rom, anything left out falls back to the defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomEntry {
+    pub name: Option<String>,
+    pub quirks: Option<QuirksPreset>,
+    pub timing: Option<Timing>,
+    pub speed: Option<u32>,
+    pub background_color: Option<u32>,
+    pub sprite_color: Option<u32>,
+    pub plane2_color: Option<u32>,
+    pub overlap_color: Option<u32>,
+    #[serde(default)]
+    pub keys: BTreeMap<String, u8>,
+    #[serde(default)]
+    pub buttons: BTreeMap<String, u8>,
+}
+
+impl RomEntry {
+    /// The default keymap with this rom's keys and buttons applied.
+    pub fn keymap(&self) -> Keymap {
+        Keymap::default()
+            .with_overrides(&self.keys, &self.buttons)
+            .expect("Rom database keys are checked when it's loaded")
+    }
+}
+
+/// Per-rom settings keyed by the SHA-1 of the rom, like the
+/// [CHIP-8 database](https://github.com/chip-8/chip-8-database):
+///
+/// ```toml
+/// [0df2789f661358d8f7370e6cf93490c5bcd44b01]
+/// name = "Example"
+/// quirks = "super-chip"
+/// timing = "ipf"
+/// speed = 15
+/// sprite_color = 0x33FF66
+///
+/// [0df2789f661358d8f7370e6cf93490c5bcd44b01.keys]
+/// Up = 0x1
+/// Down = 0x4
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RomDatabase {
+    roms: HashMap<String, RomEntry>,
+}
+
+impl RomDatabase {
+    pub fn load(path: &Path) -> Result<Self, FileError> {
+        file_error::load(path, Self::parse)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let entries: HashMap<String, RomEntry> =
+            toml::from_str(text).map_err(|error| error.message().to_string())?;
+
+        let mut roms = HashMap::new();
+        for (hash, entry) in entries {
+            let is_sha1 = hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit());
+            if !is_sha1 {
+                return Err(format!("{hash} isn't a SHA-1 hash"));
+            }
+            Keymap::default()
+                .with_overrides(&entry.keys, &entry.buttons)
+                .map_err(|message| format!("{hash}: {message}"))?;
+            roms.insert(hash.to_ascii_lowercase(), entry);
+        }
+        Ok(RomDatabase { roms })
+    }
+
+    pub fn lookup(&self, rom_data: &[u8]) -> Option<&RomEntry> {
+        self.roms.get(&rom_hash(rom_data))
+    }
+}
+
+/// Lowercase hex SHA-1 of a rom.
+pub fn rom_hash(rom_data: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom_data).digest().to_string()
+}
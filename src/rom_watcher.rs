@@ -0,0 +1,52 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Notices when a rom file is rewritten by polling its modification time.
+pub struct RomWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    next_poll: Instant,
+    // Only report a missing file once until it's back
+    reported_error: bool,
+}
+
+impl RomWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        RomWatcher {
+            path,
+            modified,
+            next_poll: Instant::now() + POLL_INTERVAL,
+            reported_error: false,
+        }
+    }
+
+    /// Returns `true` once for each change, cheap enough to call every frame.
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_poll {
+            return false;
+        }
+        self.next_poll = now + POLL_INTERVAL;
+
+        match fs::metadata(&self.path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => {
+                self.reported_error = false;
+                self.modified.replace(modified) != Some(modified)
+            }
+            Err(error) => {
+                if !self.reported_error {
+                    eprintln!("Couldn't check {}: {error}", self.path.display());
+                    self.reported_error = true;
+                }
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use crate::chip8::Chip8Error;
+
+pub const MAGIC: &[u8; 4] = b"C8SS";
+pub const VERSION: u8 = 3;
+
+/// Appends little-endian fields to a save state buffer.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        StateWriter { data }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u128(&mut self, value: u128) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn write_bools(&mut self, bools: &[bool]) {
+        self.data.extend(bools.iter().map(|value| *value as u8));
+    }
+
+    pub fn write_duration(&mut self, duration: Duration) {
+        self.write_u64(duration.as_nanos() as u64);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads fields back in the order `StateWriter` wrote them.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, Chip8Error> {
+        let mut reader = StateReader { data };
+        if reader.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(Chip8Error::InvalidSaveState {
+                reason: "not a save state",
+            });
+        }
+        if reader.read_u8()? != VERSION {
+            return Err(Chip8Error::InvalidSaveState {
+                reason: "unsupported save state version",
+            });
+        }
+        Ok(reader)
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Chip8Error> {
+        if self.data.len() < len {
+            return Err(Chip8Error::InvalidSaveState {
+                reason: "save state is truncated",
+            });
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, Chip8Error> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, Chip8Error> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u128(&mut self) -> Result<u128, Chip8Error> {
+        Ok(u128::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], Chip8Error> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn read_bools(&mut self, bools: &mut [bool]) -> Result<(), Chip8Error> {
+        let bytes = self.read_bytes(bools.len())?;
+        for (value, byte) in bools.iter_mut().zip(bytes) {
+            *value = *byte != 0;
+        }
+        Ok(())
+    }
+
+    pub fn read_duration(&mut self) -> Result<Duration, Chip8Error> {
+        Ok(Duration::from_nanos(self.read_u64()?))
+    }
+
+    pub fn finish(self) -> Result<(), Chip8Error> {
+        if !self.data.is_empty() {
+            return Err(Chip8Error::InvalidSaveState {
+                reason: "unexpected data after save state",
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chip8::FRAME_DURATION;
+
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 11;
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = 700;
+
+/// How long each instruction takes to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TimingModel {
+    /// Per-opcode costs measured on the COSMAC VIP.
+    #[default]
+    Vip,
+    /// The same number of instructions in every 60Hz frame.
+    InstructionsPerFrame(u32),
+    /// The same number of instructions every second.
+    InstructionsPerSecond(u32),
+}
+
+impl TimingModel {
+    /// Duration of an instruction that takes `vip_micros` on the VIP.
+    pub fn instruction_duration(self, vip_micros: u64) -> Duration {
+        match self {
+            TimingModel::Vip => Duration::from_micros(vip_micros),
+            TimingModel::InstructionsPerFrame(count) => FRAME_DURATION / count.max(1),
+            TimingModel::InstructionsPerSecond(count) => Duration::from_secs(1) / count.max(1),
+        }
+    }
+
+    /// Runs about a quarter more instructions, VIP timing can't be sped up.
+    pub fn faster(self) -> Self {
+        self.with_count(|count| (count + count / 4).max(count + 1))
+    }
+
+    /// Runs about a fifth fewer instructions, VIP timing can't be slowed down.
+    pub fn slower(self) -> Self {
+        self.with_count(|count| (count - count / 5).min(count - 1).max(1))
+    }
+
+    /// The next model in VIP, per frame, per second order, at its default speed.
+    pub fn next(self) -> Self {
+        match self {
+            TimingModel::Vip => TimingModel::InstructionsPerFrame(DEFAULT_INSTRUCTIONS_PER_FRAME),
+            TimingModel::InstructionsPerFrame(_) => {
+                TimingModel::InstructionsPerSecond(DEFAULT_INSTRUCTIONS_PER_SECOND)
+            }
+            TimingModel::InstructionsPerSecond(_) => TimingModel::Vip,
+        }
+    }
+
+    fn with_count(self, change: impl Fn(u32) -> u32) -> Self {
+        match self {
+            TimingModel::Vip => TimingModel::Vip,
+            TimingModel::InstructionsPerFrame(count) => {
+                TimingModel::InstructionsPerFrame(change(count.max(1)))
+            }
+            TimingModel::InstructionsPerSecond(count) => {
+                TimingModel::InstructionsPerSecond(change(count.max(1)))
+            }
+        }
+    }
+}
+
+impl fmt::Display for TimingModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimingModel::Vip => write!(f, "COSMAC VIP instruction timing"),
+            TimingModel::InstructionsPerFrame(count) => {
+                write!(f, "{count} instructions per frame")
+            }
+            TimingModel::InstructionsPerSecond(count) => {
+                write!(f, "{count} instructions per second")
+            }
+        }
+    }
+}